@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use futures_util::{SinkExt, StreamExt};
+use protocol::{decode_frame, decompress_frame, ServerEvent, ServerInfo, ServerMessage, ZSTD_PROTOCOL};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http, http::HeaderValue, Message};
+use crate::{ClientError, ClientOptions, Reply};
+
+type PendingRequests = Arc<Mutex<HashMap<String, oneshot::Sender<Reply>>>>;
+
+/// A single WebSocket connection to the server. Responses are routed back to
+/// the request that caused them by their request ID.
+pub(crate) struct Connection {
+  pub server_info: ServerInfo,
+  /// Whether the server accepted zstd compression of binary frames.
+  pub zstd: bool,
+  sender: mpsc::UnboundedSender<Message>,
+  pending: PendingRequests,
+  closed: Arc<AtomicBool>,
+  tasks: [JoinHandle<()>; 2],
+}
+
+impl Connection {
+  /// Connects to the server and waits for its `server_info` message. Events
+  /// received from the server are sent to `events`.
+  pub async fn open(url: &str, options: &ClientOptions, events: broadcast::Sender<ServerEvent>) -> Result<Self, ClientError> {
+    let mut request = url.into_client_request()?;
+    if options.zstd {
+      request.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(ZSTD_PROTOCOL));
+    }
+    if let Some(token) = &options.auth_token {
+      let value = HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|e| tungstenite::Error::from(http::Error::from(e)))?;
+      request.headers_mut().insert("Authorization", value);
+    }
+    let (ws_stream, handshake) = match connect_async(request).await {
+      Ok(connected) => connected,
+      Err(tungstenite::Error::Http(response)) if response.status() == http::StatusCode::UNAUTHORIZED => {
+        return Err(ClientError::InvalidToken);
+      }
+      Err(e) => return Err(e.into()),
+    };
+    let zstd = handshake.headers().get("Sec-WebSocket-Protocol")
+      .is_some_and(|protocol| protocol == ZSTD_PROTOCOL);
+    let (mut write, mut read) = ws_stream.split();
+
+    let server_info = loop {
+      match read.next().await {
+        Some(Ok(Message::Text(text))) => {
+          match serde_json::from_str::<ServerEvent>(&text) {
+            Ok(ServerEvent::ServerInfo(info)) => break info,
+            Ok(_) => continue,
+            Err(_) => return Err(ClientError::MissingServerInfo),
+          }
+        }
+        Some(Ok(Message::Close(_))) | None => return Err(ClientError::Disconnected),
+        Some(Ok(_)) => continue,
+        Some(Err(e)) => return Err(e.into()),
+      }
+    };
+    if let Some(error) = server_info.error {
+      return Err(ClientError::UnsupportedGame(error));
+    }
+
+    let pending: PendingRequests = Arc::new(Mutex::new(HashMap::new()));
+    let closed = Arc::new(AtomicBool::new(false));
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+
+    let write_closed = closed.clone();
+    let write_task = tokio::spawn(async move {
+      while let Some(msg) = receiver.recv().await {
+        if write.send(msg).await.is_err() {
+          break;
+        }
+      }
+      write_closed.store(true, Ordering::SeqCst);
+    });
+
+    let read_pending = pending.clone();
+    let read_closed = closed.clone();
+    let max_message_size = options.max_message_size;
+    let read_task = tokio::spawn(async move {
+      while let Some(Ok(msg)) = read.next().await {
+        let (text, attachments) = match msg {
+          Message::Text(text) => (text, Vec::new()),
+          Message::Binary(bytes) => {
+            let bytes = if zstd {
+              match decompress_frame(&bytes, max_message_size) {
+                Ok(bytes) => bytes,
+                Err(_) => continue,
+              }
+            } else {
+              bytes
+            };
+            match decode_frame(&bytes) {
+              Ok(frame) => (
+                frame.header.to_string(),
+                frame.attachments.iter().map(|a| a.to_vec()).collect(),
+              ),
+              Err(_) => continue,
+            }
+          }
+          Message::Close(_) => break,
+          _ => continue,
+        };
+        match serde_json::from_str::<ServerMessage>(&text) {
+          Ok(ServerMessage::Response(response)) => {
+            let waiter = read_pending.lock().unwrap().remove(&response.request_id);
+            if let Some(waiter) = waiter {
+              let _ = waiter.send(Reply { response, attachments });
+            }
+          }
+          Ok(ServerMessage::Event(event)) => {
+            // Fails if nobody is listening for events, which is fine
+            let _ = events.send(event);
+          }
+          Err(_) => {}
+        }
+      }
+      read_closed.store(true, Ordering::SeqCst);
+      // Dropping the senders makes every request still waiting fail with
+      // ClientError::Disconnected.
+      read_pending.lock().unwrap().clear();
+    });
+
+    Ok(Self {
+      server_info,
+      zstd,
+      sender,
+      pending,
+      closed,
+      tasks: [write_task, read_task],
+    })
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
+
+  /// Sends a request and waits for its response.
+  pub async fn send(&self, request_id: String, message: Message) -> Result<Reply, ClientError> {
+    let (tx, rx) = oneshot::channel();
+    self.pending.lock().unwrap().insert(request_id.clone(), tx);
+    if self.is_closed() || self.sender.send(message).is_err() {
+      self.pending.lock().unwrap().remove(&request_id);
+      return Err(ClientError::Disconnected);
+    }
+    rx.await.map_err(|_| ClientError::Disconnected)
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    for task in &self.tasks {
+      task.abort();
+    }
+  }
+}
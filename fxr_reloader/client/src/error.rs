@@ -0,0 +1,48 @@
+use protocol::{ErrorCode, ResponseError};
+use thiserror::Error;
+use tokio_tungstenite::tungstenite;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+  #[error("WebSocket error: {0}")]
+  WebSocket(Box<tungstenite::Error>),
+  #[error("The server did not send a server_info message.")]
+  MissingServerInfo,
+  #[error("The server failed to detect a supported game: {0}")]
+  UnsupportedGame(String),
+  #[error("The connection to the server was lost.")]
+  Disconnected,
+  #[error("The server rejected the auth token.")]
+  InvalidToken,
+  /// The server responded with an error. `error` is `None` for servers that
+  /// predate error codes.
+  #[error("Request failed: {message}")]
+  RequestFailed {
+    message: String,
+    error: Option<ResponseError>,
+  },
+  #[error("The server sent back unexpected data for a {0} request.")]
+  UnexpectedResponse(&'static str),
+  #[error("Failed to decode base64 FXR: {0}")]
+  InvalidBase64(#[from] base64::DecodeError),
+  #[error("The server referred to FXR attachment {0}, which it did not send.")]
+  MissingAttachment(u32),
+  #[error("Invalid JSON: {0}")]
+  Json(#[from] serde_json::Error),
+}
+
+impl ClientError {
+  /// The error code sent by the server, if the request failed.
+  pub fn code(&self) -> Option<ErrorCode> {
+    match self {
+      ClientError::RequestFailed { error: Some(error), .. } => Some(error.code),
+      _ => None,
+    }
+  }
+}
+
+impl From<tungstenite::Error> for ClientError {
+  fn from(e: tungstenite::Error) -> Self {
+    ClientError::WebSocket(Box::new(e))
+  }
+}
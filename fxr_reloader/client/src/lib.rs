@@ -0,0 +1,519 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use base64::{engine::general_purpose, Engine as _};
+use protocol::{compress_frame, encode_frame, BatchResult, Capabilities, EventTopic, FxrData, LogLevel, Request, RequestPayload, Response, ResponseData, ServerEvent, ServerInfo, Stats};
+use tokio::sync::{broadcast, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+use connection::Connection;
+
+mod connection;
+mod error;
+
+pub use error::ClientError;
+pub use protocol;
+
+pub const DEFAULT_URL: &str = "ws://127.0.0.1:24621";
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+  /// How many times to try reconnecting when a request is made after the
+  /// connection was lost.
+  pub reconnect_attempts: u32,
+  /// How long to wait between reconnect attempts.
+  pub reconnect_delay: Duration,
+  /// Send requests in binary frames so that FXRs are sent and received as
+  /// raw bytes instead of base64 strings.
+  pub binary_frames: bool,
+  /// Offer zstd compression of binary frames to the server. Text frames are
+  /// never compressed, so this is only useful along with `binary_frames`.
+  pub zstd: bool,
+  /// How long the server may take to handle each request. Requests that take
+  /// longer fail with [`ErrorCode::TimedOut`](protocol::ErrorCode::TimedOut).
+  pub timeout: Option<Duration>,
+  /// Token to authenticate with, for servers that require one.
+  pub auth_token: Option<String>,
+  /// The largest a compressed binary frame from the server may be once it is
+  /// decompressed. Larger frames are ignored.
+  pub max_message_size: usize,
+}
+
+impl Default for ClientOptions {
+  fn default() -> Self {
+    Self {
+      reconnect_attempts: 5,
+      reconnect_delay: Duration::from_millis(500),
+      binary_frames: false,
+      zstd: false,
+      timeout: None,
+      auth_token: None,
+      max_message_size: 64 << 20,
+    }
+  }
+}
+
+/// A response along with the attachments of the binary frame it was sent in.
+#[derive(Debug)]
+pub struct Reply {
+  pub response: Response,
+  pub attachments: Vec<Vec<u8>>,
+}
+
+impl Reply {
+  /// Gets the bytes of an FXR sent back in the response.
+  pub fn fxr(&self, fxr: FxrData) -> Result<Vec<u8>, ClientError> {
+    match fxr {
+      FxrData::Base64(base64_str) => Ok(general_purpose::STANDARD.decode(base64_str)?),
+      FxrData::Attachment { attachment } => self.attachments.get(attachment as usize)
+        .cloned()
+        .ok_or(ClientError::MissingAttachment(attachment)),
+    }
+  }
+
+  /// The results of the requests in a batch, if this is the reply to one.
+  pub fn batch_results(&self) -> Option<&[BatchResult]> {
+    match &self.response.data {
+      Some(ResponseData::Batch { results }) => Some(results),
+      _ => None,
+    }
+  }
+}
+
+/// An async client for the reloader's WebSocket server.
+///
+/// The client can be cloned cheaply to share it between tasks. If the
+/// connection is lost, the next request will try to reconnect. Requests that
+/// were in flight when the connection was lost fail with
+/// [`ClientError::Disconnected`] instead of being resent. Event subscriptions
+/// are renewed when the client reconnects.
+#[derive(Clone)]
+pub struct Client {
+  inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+  url: String,
+  options: ClientOptions,
+  connection: Mutex<Arc<Connection>>,
+  server_info: std::sync::Mutex<ServerInfo>,
+  next_request_id: AtomicU64,
+  events: broadcast::Sender<ServerEvent>,
+  subscriptions: std::sync::Mutex<HashSet<EventTopic>>,
+  /// The level passed to `subscribe_logs`, while subscribed to logs that way.
+  log_level: std::sync::Mutex<Option<LogLevel>>,
+}
+
+impl Client {
+  /// Connects to the server at the given URL, for example [`DEFAULT_URL`].
+  pub async fn connect(url: impl Into<String>) -> Result<Self, ClientError> {
+    Self::connect_with_options(url, ClientOptions::default()).await
+  }
+
+  pub async fn connect_with_options(url: impl Into<String>, options: ClientOptions) -> Result<Self, ClientError> {
+    let url = url.into();
+    let (events, _) = broadcast::channel(256);
+    let connection = Connection::open(&url, &options, events.clone()).await?;
+    Ok(Self {
+      inner: Arc::new(ClientInner {
+        url,
+        options,
+        server_info: std::sync::Mutex::new(connection.server_info.clone()),
+        connection: Mutex::new(Arc::new(connection)),
+        next_request_id: AtomicU64::new(1),
+        events,
+        subscriptions: std::sync::Mutex::new(HashSet::new()),
+        log_level: std::sync::Mutex::new(None),
+      }),
+    })
+  }
+
+  /// The `server_info` message received from the server when the client last
+  /// connected.
+  pub fn server_info(&self) -> ServerInfo {
+    self.inner.server_info.lock().unwrap().clone()
+  }
+
+  /// Receives the events sent by the server, like the ones that have been
+  /// subscribed to with [`subscribe`](Self::subscribe).
+  pub fn events(&self) -> broadcast::Receiver<ServerEvent> {
+    self.inner.events.subscribe()
+  }
+
+  /// Reconnects if the connection has been lost. Requests do this on their
+  /// own, but clients that only listen for events need to do it themselves.
+  pub async fn ensure_connected(&self) -> Result<(), ClientError> {
+    self.connection().await.map(|_| ())
+  }
+
+  async fn connection(&self) -> Result<Arc<Connection>, ClientError> {
+    let mut connection = self.inner.connection.lock().await;
+    if !connection.is_closed() {
+      return Ok(connection.clone());
+    }
+
+    let mut attempt = 0;
+    loop {
+      let opened = Connection::open(&self.inner.url, &self.inner.options, self.inner.events.clone()).await;
+      match opened {
+        Ok(new_connection) => {
+          *self.inner.server_info.lock().unwrap() = new_connection.server_info.clone();
+          let new_connection = Arc::new(new_connection);
+          let events: Vec<EventTopic> = self.inner.subscriptions.lock().unwrap().iter().copied().collect();
+          if !events.is_empty() {
+            let (request_id, message) = self.encode(&new_connection, RequestPayload::Subscribe { events }, Vec::new())?;
+            new_connection.send(request_id, message).await?;
+          }
+          let log_level = *self.inner.log_level.lock().unwrap();
+          if let Some(level) = log_level {
+            let (request_id, message) = self.encode(&new_connection, RequestPayload::SubscribeLogs { level: Some(level) }, Vec::new())?;
+            new_connection.send(request_id, message).await?;
+          }
+          *connection = new_connection;
+          return Ok(connection.clone());
+        }
+        Err(e) => {
+          if attempt >= self.inner.options.reconnect_attempts {
+            return Err(e);
+          }
+          attempt += 1;
+          tokio::time::sleep(self.inner.options.reconnect_delay).await;
+        }
+      }
+    }
+  }
+
+  /// Sends a request and waits for the response. Responses that indicate
+  /// failure are turned into [`ClientError::RequestFailed`].
+  ///
+  /// The request is sent in a binary frame if there are attachments or if
+  /// [`ClientOptions::binary_frames`] is enabled.
+  pub async fn request(&self, payload: RequestPayload, attachments: Vec<Vec<u8>>) -> Result<Reply, ClientError> {
+    let reply = self.send(payload, attachments).await?;
+    if !reply.response.success {
+      return Err(ClientError::RequestFailed {
+        message: reply.response.message,
+        error: reply.response.error,
+      });
+    }
+    Ok(reply)
+  }
+
+  async fn send(&self, payload: RequestPayload, attachments: Vec<Vec<u8>>) -> Result<Reply, ClientError> {
+    let connection = self.connection().await?;
+    let (request_id, message) = self.encode(&connection, payload, attachments)?;
+    connection.send(request_id, message).await
+  }
+
+  fn encode(
+    &self,
+    connection: &Connection,
+    payload: RequestPayload,
+    attachments: Vec<Vec<u8>>,
+  ) -> Result<(String, Message), ClientError> {
+    let request_id = format!("client-{}", self.inner.next_request_id.fetch_add(1, Ordering::Relaxed));
+    let text = serde_json::to_string(&Request {
+      request_id: request_id.clone(),
+      timeout_ms: self.inner.options.timeout.map(|timeout| timeout.as_millis() as u64),
+      payload,
+    })?;
+    let message = if self.inner.options.binary_frames || !attachments.is_empty() {
+      let frame = encode_frame(&text, &attachments);
+      Message::Binary(if connection.zstd { compress_frame(&frame) } else { frame })
+    } else {
+      Message::Text(text)
+    };
+    Ok((request_id, message))
+  }
+
+  /// Replaces the definitions of the given FXRs in the game.
+  pub async fn reload_fxrs<T: AsRef<[u8]>>(&self, fxrs: &[T]) -> Result<(), ClientError> {
+    if self.inner.options.binary_frames {
+      let refs = (0..fxrs.len() as u32)
+        .map(|attachment| FxrData::Attachment { attachment })
+        .collect();
+      let attachments = fxrs.iter().map(|fxr| fxr.as_ref().to_vec()).collect();
+      self.request(RequestPayload::ReloadFxrs { fxrs: refs }, attachments).await?;
+    } else {
+      let fxrs = fxrs.iter()
+        .map(|fxr| FxrData::Base64(general_purpose::STANDARD.encode(fxr)))
+        .collect();
+      self.request(RequestPayload::ReloadFxrs { fxrs }, Vec::new()).await?;
+    }
+    Ok(())
+  }
+
+  /// Extracts a loaded FXR from the game's memory.
+  pub async fn get_fxr(&self, id: u32) -> Result<Vec<u8>, ClientError> {
+    let mut reply = self.request(RequestPayload::GetFxr { id }, Vec::new()).await?;
+    match reply.response.data.take() {
+      Some(ResponseData::Fxr { fxr }) => reply.fxr(fxr),
+      _ => Err(ClientError::UnexpectedResponse("get_fxr")),
+    }
+  }
+
+  /// Extracts multiple loaded FXRs from the game's memory. The returned list
+  /// is in the same order as the IDs, with `None` for IDs that could not be
+  /// found.
+  pub async fn get_fxrs(&self, ids: &[u32]) -> Result<Vec<Option<Vec<u8>>>, ClientError> {
+    let mut reply = self.request(RequestPayload::GetFxrs { ids: ids.to_vec() }, Vec::new()).await?;
+    match reply.response.data.take() {
+      Some(ResponseData::Fxrs { fxrs }) => fxrs.into_iter()
+        .map(|fxr| fxr.map(|fxr| reply.fxr(fxr)).transpose())
+        .collect(),
+      _ => Err(ClientError::UnexpectedResponse("get_fxrs")),
+    }
+  }
+
+  /// Lists the IDs of all loaded FXRs.
+  pub async fn list_fxrs(&self) -> Result<Vec<u32>, ClientError> {
+    let response = self.request(RequestPayload::ListFxrs, Vec::new()).await?.response;
+    match response.data {
+      Some(ResponseData::FxrIds { ids }) => Ok(ids),
+      _ => Err(ClientError::UnexpectedResponse("list_fxrs")),
+    }
+  }
+
+  /// Gets the protocol version, supported request types and features of the
+  /// server. Servers that predate this request fail with
+  /// [`ClientError::RequestFailed`], and don't send a protocol version in
+  /// their [`server_info`](Self::server_info).
+  pub async fn capabilities(&self) -> Result<Capabilities, ClientError> {
+    let response = self.request(RequestPayload::GetCapabilities, Vec::new()).await?.response;
+    match response.data {
+      Some(ResponseData::Capabilities { capabilities }) => Ok(capabilities),
+      _ => Err(ClientError::UnexpectedResponse("get_capabilities")),
+    }
+  }
+
+  /// Gets counters and request latencies collected since the server started.
+  pub async fn stats(&self) -> Result<Stats, ClientError> {
+    let response = self.request(RequestPayload::GetStats, Vec::new()).await?.response;
+    match response.data {
+      Some(ResponseData::Stats { stats }) => Ok(stats),
+      _ => Err(ClientError::UnexpectedResponse("get_stats")),
+    }
+  }
+
+  /// Asks the server to close every connection, including this one, and stop.
+  /// Requires the `read_write` role.
+  pub async fn shutdown(&self) -> Result<(), ClientError> {
+    self.request(RequestPayload::Shutdown, Vec::new()).await?;
+    Ok(())
+  }
+
+  /// Runs multiple requests in order. If `stop_on_error` is true, the server
+  /// checks that every request is supported before running any of them, and
+  /// stops at the first one that fails. Requests that ran before it are not
+  /// undone.
+  ///
+  /// The reply's [`batch_results`](Reply::batch_results) are returned even if
+  /// some of the requests failed, so check the result of each one. The batch
+  /// only fails with [`ClientError::RequestFailed`] if it could not be run.
+  pub async fn batch(
+    &self,
+    requests: Vec<RequestPayload>,
+    attachments: Vec<Vec<u8>>,
+    stop_on_error: bool,
+  ) -> Result<Reply, ClientError> {
+    let payload = RequestPayload::Batch { requests, stop_on_error: Some(stop_on_error) };
+    let reply = self.send(payload, attachments).await?;
+    if reply.batch_results().is_none() {
+      return Err(ClientError::RequestFailed {
+        message: reply.response.message,
+        error: reply.response.error,
+      });
+    }
+    Ok(reply)
+  }
+
+  /// Starts receiving events of the given kinds from [`events`](Self::events).
+  pub async fn subscribe(&self, events: &[EventTopic]) -> Result<(), ClientError> {
+    self.request(RequestPayload::Subscribe { events: events.to_vec() }, Vec::new()).await?;
+    self.inner.subscriptions.lock().unwrap().extend(events);
+    Ok(())
+  }
+
+  /// Stops receiving events of the given kinds.
+  pub async fn unsubscribe(&self, events: &[EventTopic]) -> Result<(), ClientError> {
+    self.request(RequestPayload::Unsubscribe { events: events.to_vec() }, Vec::new()).await?;
+    let mut subscriptions = self.inner.subscriptions.lock().unwrap();
+    for event in events {
+      subscriptions.remove(event);
+    }
+    if events.contains(&EventTopic::Logs) {
+      *self.inner.log_level.lock().unwrap() = None;
+    }
+    Ok(())
+  }
+
+  /// Starts receiving the server's log records of the given level and above
+  /// from [`events`](Self::events) as [`ServerEvent::Log`]. Stop with
+  /// [`unsubscribe`](Self::unsubscribe) and [`EventTopic::Logs`].
+  pub async fn subscribe_logs(&self, level: LogLevel) -> Result<(), ClientError> {
+    self.request(RequestPayload::SubscribeLogs { level: Some(level) }, Vec::new()).await?;
+    *self.inner.log_level.lock().unwrap() = Some(level);
+    Ok(())
+  }
+
+  /// Sets the resident SFX of a weapon, respawning the effect.
+  pub async fn set_resident_sfx(&self, weapon: u32, sfx: i32, dmy: i32) -> Result<(), ClientError> {
+    self.request(RequestPayload::SetResidentSfx { weapon, sfx, dmy }, Vec::new()).await?;
+    Ok(())
+  }
+
+  /// Sets the SFX of a SpEffect's VFX, respawning the effect. If `vfx` is
+  /// given, the SpEffect's VFX ID is also changed to it.
+  pub async fn set_sp_effect_sfx(
+    &self,
+    sp_effect: u32,
+    sfx: i32,
+    dmy: i16,
+    vfx: Option<i32>,
+  ) -> Result<(), ClientError> {
+    self.request(RequestPayload::SetSpEffectSfx { sp_effect, sfx, dmy, vfx }, Vec::new()).await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures_util::{SinkExt, StreamExt};
+  use protocol::{ErrorCode, ResponseError};
+  use tokio::net::{TcpListener, TcpStream};
+  use tokio::sync::mpsc;
+  use tokio_tungstenite::WebSocketStream;
+  use super::*;
+
+  type ServerStream = WebSocketStream<TcpStream>;
+
+  /// Starts a server that greets every client with a `server_info` message,
+  /// giving them increasing client IDs, and hands the connections to the
+  /// test in the order they were made.
+  async fn serve() -> (String, mpsc::UnboundedReceiver<ServerStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+      for client_id in 1.. {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let info = ServerEvent::ServerInfo(ServerInfo {
+          version: "test".to_string(),
+          protocol_version: Some(protocol::PROTOCOL_VERSION),
+          game: None,
+          features: None,
+          client_id: Some(client_id),
+          role: None,
+          error: None,
+        });
+        ws.send(Message::Text(serde_json::to_string(&info).unwrap())).await.unwrap();
+        if tx.send(ws).is_err() {
+          break;
+        }
+      }
+    });
+    (url, rx)
+  }
+
+  async fn next_request(ws: &mut ServerStream) -> Request {
+    loop {
+      if let Message::Text(text) = ws.next().await.unwrap().unwrap() {
+        return serde_json::from_str(&text).unwrap();
+      }
+    }
+  }
+
+  async fn respond(ws: &mut ServerStream, request: &Request, data: Option<ResponseData>) {
+    let response = Response {
+      request_id: request.request_id.clone(),
+      success: true,
+      message: "ok".to_string(),
+      data,
+      error: None,
+    };
+    ws.send(Message::Text(serde_json::to_string(&response).unwrap())).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn matches_responses_to_requests_by_id() {
+    let (url, mut connections) = serve().await;
+    let client = Client::connect(url).await.unwrap();
+    let mut ws = connections.recv().await.unwrap();
+    assert_eq!(client.server_info().client_id, Some(1));
+
+    let server = async {
+      let first = next_request(&mut ws).await;
+      let second = next_request(&mut ws).await;
+      respond(&mut ws, &second, Some(ResponseData::FxrIds { ids: vec![2] })).await;
+      respond(&mut ws, &first, Some(ResponseData::FxrIds { ids: vec![1] })).await;
+    };
+    let (first, second, ()) = tokio::join!(client.list_fxrs(), client.list_fxrs(), server);
+    assert_eq!(first.unwrap(), [1]);
+    assert_eq!(second.unwrap(), [2]);
+  }
+
+  #[tokio::test]
+  async fn reconnects_and_subscribes_again() {
+    let (url, mut connections) = serve().await;
+    let client = Client::connect(url).await.unwrap();
+    let mut ws = connections.recv().await.unwrap();
+    let server = async {
+      let request = next_request(&mut ws).await;
+      respond(&mut ws, &request, None).await;
+    };
+    let (subscribed, ()) = tokio::join!(client.subscribe(&[EventTopic::FxrsChanged]), server);
+    subscribed.unwrap();
+    drop(ws);
+
+    let list = tokio::spawn({
+      let client = client.clone();
+      async move {
+        // Requests sent before the client notices the connection was lost
+        // fail without being resent
+        loop {
+          match client.list_fxrs().await {
+            Err(ClientError::Disconnected) => continue,
+            result => return result,
+          }
+        }
+      }
+    });
+    let mut ws = connections.recv().await.unwrap();
+    let request = next_request(&mut ws).await;
+    assert!(matches!(&request.payload, RequestPayload::Subscribe { events } if events == &[EventTopic::FxrsChanged]));
+    respond(&mut ws, &request, None).await;
+    let request = next_request(&mut ws).await;
+    assert!(matches!(request.payload, RequestPayload::ListFxrs));
+    respond(&mut ws, &request, Some(ResponseData::FxrIds { ids: vec![7] })).await;
+
+    assert_eq!(list.await.unwrap().unwrap(), [7]);
+    assert_eq!(client.server_info().client_id, Some(2));
+  }
+
+  #[tokio::test]
+  async fn maps_error_responses_to_request_failed() {
+    let (url, mut connections) = serve().await;
+    let client = Client::connect(url).await.unwrap();
+    let mut ws = connections.recv().await.unwrap();
+    let server = async {
+      let request = next_request(&mut ws).await;
+      let response = Response {
+        request_id: request.request_id,
+        success: false,
+        message: "FXR 5 not found".to_string(),
+        data: None,
+        error: Some(ResponseError { fxr_id: Some(5), ..ResponseError::new(ErrorCode::FxrNotFound) }),
+      };
+      ws.send(Message::Text(serde_json::to_string(&response).unwrap())).await.unwrap();
+    };
+    let (result, ()) = tokio::join!(client.get_fxr(5), server);
+    let error = result.unwrap_err();
+    assert_eq!(error.code(), Some(ErrorCode::FxrNotFound));
+    let ClientError::RequestFailed { message, error: Some(error) } = error else {
+      panic!("unexpected error: {:?}", error);
+    };
+    assert_eq!(message, "FXR 5 not found");
+    assert_eq!(error.fxr_id, Some(5));
+  }
+}
@@ -10,8 +10,9 @@ use crate::game::FxrManager;
 use from_singleton::{FromSingleton, address_of};
 use std::borrow::Cow;
 use pelite::pattern::Atom;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 type FxrAllocatorGetter = unsafe extern "system" fn() -> usize;
 type AllocateFxr = unsafe extern "system" fn(usize, usize, usize) -> usize;
@@ -40,12 +41,7 @@ pub unsafe extern "system" fn null_preparer(_: usize) -> *const c_void {
   std::ptr::null()
 }
 
-#[derive(serde::Serialize, Debug, Clone, Copy)]
-pub struct SupportedFeatures {
-  pub reload: bool,
-  pub params: bool,
-  pub extract: bool,
-}
+pub use protocol::SupportedFeatures;
 
 #[derive(Debug, Clone, Copy)]
 pub struct GameData {
@@ -407,6 +403,13 @@ macro_rules! define_games {
       ];
 
       static FXR_MANAGER_CACHE: Lazy<Mutex<Option<&'static (dyn FxrManager + Send + Sync)>>> = Lazy::new(|| Mutex::new(None));
+      static FXR_MANAGER_RESOLVE_TIME: OnceCell<Duration> = OnceCell::new();
+
+      /// How long it took to create the FXR manager, which involves scanning
+      /// the game for patterns. `None` until it has been created.
+      pub fn fxr_manager_resolve_time() -> Option<Duration> {
+        FXR_MANAGER_RESOLVE_TIME.get().copied()
+      }
 
       pub(crate) fn fxr_manager_for(game: &GameData) -> Result<&'static (dyn FxrManager + Send + Sync), FxrManagerError> {
         let mut cache = FXR_MANAGER_CACHE.lock().unwrap();
@@ -414,6 +417,7 @@ macro_rules! define_games {
           return Ok(manager);
         }
 
+        let started = Instant::now();
         let manager: Box<dyn FxrManager + Send + Sync> = match game.name {
           $(
             stringify!($game_ident) => Box::new([<$game_ident FxrManager>]::new()?),
@@ -421,6 +425,7 @@ macro_rules! define_games {
           _ => return Err(FxrManagerError::UnsupportedGame),
         };
         let static_manager: &'static (dyn FxrManager + Send + Sync) = Box::leak(manager);
+        let _ = FXR_MANAGER_RESOLVE_TIME.set(started.elapsed());
         *cache = Some(static_manager);
         Ok(static_manager)
       }
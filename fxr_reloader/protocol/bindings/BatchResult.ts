@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ResponseData } from "./ResponseData";
+import type { ResponseError } from "./ResponseError";
+
+/**
+ * The result of one of the requests in a batch.
+ */
+export type BatchResult = { success: boolean, message: string, data?: ResponseData, error?: ResponseError, };
@@ -0,0 +1,21 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RequestType } from "./RequestType";
+
+/**
+ * Sent back by `get_capabilities`.
+ */
+export type Capabilities = { protocol_version: number, 
+/**
+ * The request types the server supports for the current game.
+ */
+requests: Array<RequestType>, 
+/**
+ * The names of the features the server supports.
+ */
+features: Array<string>, 
+/**
+ * The names of the features in `features` that are enabled for this
+ * connection, for features like `zstd` that have to be negotiated when
+ * connecting. Not sent by servers with a protocol version below 2.
+ */
+negotiated: Array<string>, };
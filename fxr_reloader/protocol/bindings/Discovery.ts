@@ -0,0 +1,25 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Written by the server to a file in [`discovery_dir`] named after the
+ * game's process ID once it is listening, so that clients can find it. The
+ * file is removed when the server is shut down with a `shutdown` request,
+ * but is left behind if the game closes or crashes first.
+ */
+export type Discovery = { 
+/**
+ * The ID of the game's process.
+ */
+pid: number, 
+/**
+ * The URL of the WebSocket server.
+ */
+url: string, port: number, game?: string, 
+/**
+ * The version of the reloader.
+ */
+version: string, 
+/**
+ * When the server started, in seconds since the Unix epoch.
+ */
+started_at: bigint, };
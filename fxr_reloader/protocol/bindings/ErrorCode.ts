@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Stable codes for why a request failed, so clients don't have to match on
+ * the message.
+ */
+export type ErrorCode = "invalid_request" | "unknown_request_type" | "invalid_frame" | "invalid_param" | "unsupported_operation" | "unsupported_game" | "game_detection_failed" | "cssfx_missing" | "invalid_fxr" | "fxr_not_found" | "internal" | "timed_out" | "cancelled" | "request_not_found" | "unauthenticated" | "permission_denied" | "unknown";
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Kinds of events clients can subscribe to.
+ */
+export type EventTopic = "fxrs_changed" | "logs";
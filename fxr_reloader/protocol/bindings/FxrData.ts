@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * The binary data of an FXR, either as a base64 string or a reference to
+ * an attachment of the binary frame the message was sent in.
+ */
+export type FxrData = string | { 
+/**
+ * Index of the attachment in the binary frame.
+ */
+attachment: number, };
@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How long requests took to handle, in microseconds. `counts` has one more
+ * entry than `bounds_us`: each count is the number of requests that took at most the
+ * bound at the same index and longer than the one before it, and the last
+ * count is the number of requests that took longer than every bound.
+ */
+export type LatencyHistogram = { bounds_us: Array<bigint>, counts: Array<bigint>, 
+/**
+ * The total time spent on requests.
+ */
+sum_us: bigint, };
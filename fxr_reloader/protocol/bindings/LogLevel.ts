@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How important a log record is, from most to least.
+ */
+export type LogLevel = "error" | "warn" | "info" | "debug" | "trace";
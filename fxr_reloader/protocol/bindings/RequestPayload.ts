@@ -0,0 +1,24 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { EventTopic } from "./EventTopic";
+import type { FxrData } from "./FxrData";
+import type { LogLevel } from "./LogLevel";
+
+/**
+ * The request type and its parameters, tagged by the `type` property.
+ */
+export type RequestPayload = { "type": "reload_fxrs", fxrs: Array<FxrData>, } | { "type": "set_resident_sfx", weapon: number, sfx: number, dmy: number, } | { "type": "set_sp_effect_sfx", spEffect: number, sfx: number, dmy: number, vfx?: number, } | { "type": "get_fxr", id: number, } | { "type": "get_fxrs", ids: Array<number>, } | { "type": "list_fxrs" } | { "type": "get_capabilities" } | { "type": "batch", requests: Array<RequestPayload>, 
+/**
+ * Check that every request is supported before running any of them, and
+ * stop at the first one that fails. Requests that already ran are not
+ * undone. `transactional` is accepted as an alias, for older clients.
+ */
+stop_on_error?: boolean, } | { "type": "subscribe", events: Array<EventTopic>, } | { "type": "subscribe_logs", 
+/**
+ * The lowest level to send. Defaults to `info`. Records below the
+ * server's own `log_level` are never sent.
+ */
+level?: LogLevel, } | { "type": "unsubscribe", events: Array<EventTopic>, } | { "type": "cancel", 
+/**
+ * The ID of the request to cancel.
+ */
+target_request_id: string, } | { "type": "auth", token: string, } | { "type": "get_stats" } | { "type": "shutdown" };
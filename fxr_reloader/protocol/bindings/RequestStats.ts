@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ErrorCode } from "./ErrorCode";
+import type { LatencyHistogram } from "./LatencyHistogram";
+
+export type RequestStats = { request_type: string, 
+/**
+ * How many requests of this type were handled, including failed ones.
+ */
+count: bigint, 
+/**
+ * How many requests failed with each error code.
+ */
+failures: { [key in ErrorCode]?: bigint }, latency: LatencyHistogram, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A request type and the parameters it accepts.
+ */
+export type RequestType = { 
+/**
+ * The value of the `type` property for this request.
+ */
+name: string, 
+/**
+ * JSON Schema of the request's `type` and parameters. `request_id` is not
+ * included.
+ */
+schema: Record<string, unknown>, };
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ResponseData } from "./ResponseData";
+import type { ResponseError } from "./ResponseError";
+
+/**
+ * The response to a request.
+ */
+export type Response = { request_id: string, success: boolean, message: string, data?: ResponseData, 
+/**
+ * Set if the request failed.
+ */
+error?: ResponseError, };
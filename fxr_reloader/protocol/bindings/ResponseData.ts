@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BatchResult } from "./BatchResult";
+import type { Capabilities } from "./Capabilities";
+import type { FxrData } from "./FxrData";
+import type { Role } from "./Role";
+import type { Stats } from "./Stats";
+
+/**
+ * Data sent back by requests that return something.
+ */
+export type ResponseData = { fxr: FxrData, } | { ids: Array<number>, } | { fxrs: Array<FxrData | null>, } | { capabilities: Capabilities, } | { results: Array<BatchResult>, } | { role: Role, } | { stats: Stats, };
@@ -0,0 +1,27 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ErrorCode } from "./ErrorCode";
+
+/**
+ * Details about why a request failed.
+ */
+export type ResponseError = { code: ErrorCode, 
+/**
+ * The ID of the FXR the error is about.
+ */
+fxr_id?: number, 
+/**
+ * The name of the request parameter the error is about.
+ */
+param?: string, 
+/**
+ * The index of the binary frame attachment the error is about.
+ */
+attachment?: number, 
+/**
+ * The type of the request that failed, if the server could tell.
+ */
+request_type?: string, 
+/**
+ * The index of the request in a batch that failed.
+ */
+step?: number, };
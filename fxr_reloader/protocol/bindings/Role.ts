@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What a client is allowed to do. Servers without authentication give every
+ * client the `read_write` role.
+ */
+export type Role = "unauthenticated" | "read_only" | "read_write";
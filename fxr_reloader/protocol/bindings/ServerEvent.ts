@@ -0,0 +1,29 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogLevel } from "./LogLevel";
+import type { ServerInfo } from "./ServerInfo";
+
+/**
+ * Messages the server sends on its own rather than in response to a
+ * request. These are tagged by the `type` property.
+ */
+export type ServerEvent = { "type": "server_info" } & ServerInfo | { "type": "fxrs_changed", added: Array<number>, removed: Array<number>, } | { "type": "fxr_reloaded", 
+/**
+ * The IDs of the reloaded FXRs.
+ */
+ids: Array<number>, 
+/**
+ * The ID of the client that reloaded them.
+ */
+client: bigint, } | { "type": "log", level: LogLevel, 
+/**
+ * When the record was logged, in milliseconds since the Unix epoch.
+ */
+timestamp_ms: bigint, message: string, 
+/**
+ * The request being handled when the record was logged, if any.
+ */
+request_id?: string, 
+/**
+ * The client that sent that request.
+ */
+client?: bigint, };
@@ -0,0 +1,26 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Role } from "./Role";
+import type { SupportedFeatures } from "./SupportedFeatures";
+
+/**
+ * Sent by the server to every client as soon as it connects.
+ */
+export type ServerInfo = { version: string, 
+/**
+ * Not sent by servers that predate `get_capabilities`.
+ */
+protocol_version?: number, game?: string, features?: SupportedFeatures, 
+/**
+ * The ID the server gave this connection. Events caused by other clients
+ * identify them by this ID.
+ */
+client_id?: bigint, 
+/**
+ * What the client is allowed to do. Not sent by servers that predate
+ * authentication.
+ */
+role?: Role, 
+/**
+ * Set if the server failed to detect a supported game.
+ */
+error?: string, };
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Response } from "./Response";
+import type { ServerEvent } from "./ServerEvent";
+
+/**
+ * Any message the server may send to a client.
+ */
+export type ServerMessage = ServerEvent | Response;
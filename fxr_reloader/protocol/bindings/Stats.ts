@@ -0,0 +1,37 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RequestStats } from "./RequestStats";
+
+/**
+ * Sent back by `get_stats`. Counters start at zero when the server starts.
+ */
+export type Stats = { uptime_secs: bigint, 
+/**
+ * Clients that are connected right now.
+ */
+connected_clients: bigint, 
+/**
+ * Connections accepted since the server started.
+ */
+total_connections: bigint, 
+/**
+ * Size of all messages received from clients, in bytes.
+ */
+bytes_received: bigint, 
+/**
+ * Size of all messages sent to clients, in bytes.
+ */
+bytes_sent: bigint, fxrs_reloaded: bigint, fxrs_extracted: bigint, 
+/**
+ * Events that were not sent to clients because they couldn't keep up.
+ * Not sent by servers that predate it.
+ */
+events_dropped: bigint, 
+/**
+ * How long it took to find the game's FXR manager, which happens the first
+ * time a request needs it, in microseconds.
+ */
+fxr_manager_resolve_us?: bigint, 
+/**
+ * Stats for each request type that has been used.
+ */
+requests: Array<RequestStats>, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * The features a game supports.
+ */
+export type SupportedFeatures = { reload: boolean, params: boolean, extract: boolean, };
@@ -0,0 +1,32 @@
+//! Generates the JSON Schema and TypeScript definitions for the protocol.
+//!
+//! `cargo run -p protocol --example generate --features schema,typescript`
+
+use std::path::{Path, PathBuf};
+use protocol::{Discovery, Request, ServerMessage};
+use schemars::schema_for;
+use ts_rs::TS;
+
+fn write_schema(path: &Path, schema: &schemars::schema::RootSchema) {
+  let json = serde_json::to_string_pretty(schema).unwrap();
+  std::fs::write(path, json + "\n").unwrap();
+  println!("Wrote {}", path.display());
+}
+
+fn main() {
+  let out_dir = std::env::args().nth(1)
+    .map(PathBuf::from)
+    .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+
+  let schema_dir = out_dir.join("schema");
+  std::fs::create_dir_all(&schema_dir).unwrap();
+  write_schema(&schema_dir.join("request.schema.json"), &schema_for!(Request));
+  write_schema(&schema_dir.join("server_message.schema.json"), &schema_for!(ServerMessage));
+  write_schema(&schema_dir.join("discovery.schema.json"), &schema_for!(Discovery));
+
+  let bindings_dir = out_dir.join("bindings");
+  Request::export_all_to(&bindings_dir).unwrap();
+  ServerMessage::export_all_to(&bindings_dir).unwrap();
+  Discovery::export_all_to(&bindings_dir).unwrap();
+  println!("Wrote TypeScript definitions to {}", bindings_dir.display());
+}
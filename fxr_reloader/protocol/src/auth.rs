@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use crate::RequestPayload;
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+/// What a client is allowed to do. Servers without authentication give every
+/// client the `read_write` role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum Role {
+  /// The client has not authenticated yet, and can only use `auth` and
+  /// `get_capabilities`.
+  Unauthenticated,
+  /// The client can use requests that don't modify the game, like `list_fxrs`
+  /// and `get_fxr`, but can't shut the server down.
+  ReadOnly,
+  /// The client can use every request.
+  ReadWrite,
+}
+
+impl Role {
+  /// Whether a client with this role may send the request.
+  pub fn allows(self, payload: &RequestPayload) -> bool {
+    match self {
+      Role::Unauthenticated => matches!(payload, RequestPayload::Auth { .. } | RequestPayload::GetCapabilities),
+      Role::ReadOnly => !payload.is_mutating() && !matches!(payload, RequestPayload::Shutdown),
+      Role::ReadWrite => true,
+    }
+  }
+}
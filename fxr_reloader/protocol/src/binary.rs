@@ -0,0 +1,189 @@
+//! Binary WebSocket frames let FXRs be sent as raw bytes instead of base64.
+//!
+//! A binary frame is a JSON header followed by any number of attachments,
+//! each prefixed by its length as a little-endian u32:
+//!
+//! `[header length][header][attachment length][attachment]...`
+//!
+//! The header is a regular request or response, where FXRs may be given as
+//! [`FxrData::Attachment`] to refer to an attachment by its index.
+//!
+//! If the client offers the [`ZSTD_PROTOCOL`] subprotocol during the WebSocket
+//! handshake and the server accepts it, every binary frame sent over the
+//! connection, in either direction, is compressed with zstd as a whole.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+/// The binary data of an FXR, either as a base64 string or a reference to
+/// an attachment of the binary frame the message was sent in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum FxrData {
+  Base64(String),
+  Attachment {
+    /// Index of the attachment in the binary frame.
+    attachment: u32,
+  },
+}
+
+/// WebSocket subprotocol used to negotiate zstd compression of binary frames.
+pub const ZSTD_PROTOCOL: &str = "fxr-reloader.zstd";
+
+#[derive(Debug, Error)]
+pub enum FrameError {
+  #[error("Binary frame ended unexpectedly.")]
+  Truncated,
+  #[error("Binary frame header is not valid UTF-8.")]
+  InvalidHeader,
+  #[error("Failed to decompress binary frame: {0}")]
+  Decompress(std::io::Error),
+  #[error("Binary frame is larger than {0} bytes once decompressed.")]
+  TooLarge(usize),
+}
+
+#[derive(Debug)]
+pub struct BinaryFrame<'a> {
+  pub header: &'a str,
+  pub attachments: Vec<&'a [u8]>,
+}
+
+fn read_part<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], FrameError> {
+  if bytes.len() < 4 {
+    return Err(FrameError::Truncated);
+  }
+  let len = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+  // Checked so that a huge length can't overflow on 32-bit targets
+  let end = len.checked_add(4).ok_or(FrameError::Truncated)?;
+  let part = bytes.get(4..end).ok_or(FrameError::Truncated)?;
+  *bytes = &bytes[end..];
+  Ok(part)
+}
+
+pub fn decode_frame(mut bytes: &[u8]) -> Result<BinaryFrame<'_>, FrameError> {
+  let header = std::str::from_utf8(read_part(&mut bytes)?)
+    .map_err(|_| FrameError::InvalidHeader)?;
+  let mut attachments = Vec::new();
+  while !bytes.is_empty() {
+    attachments.push(read_part(&mut bytes)?);
+  }
+  Ok(BinaryFrame { header, attachments })
+}
+
+pub fn encode_frame<T: AsRef<[u8]>>(header: &str, attachments: &[T]) -> Vec<u8> {
+  let size = 4 + header.len() + attachments.iter().map(|a| 4 + a.as_ref().len()).sum::<usize>();
+  let mut bytes = Vec::with_capacity(size);
+  for part in std::iter::once(header.as_bytes()).chain(attachments.iter().map(|a| a.as_ref())) {
+    bytes.extend_from_slice(&(part.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(part);
+  }
+  bytes
+}
+
+#[cfg(feature = "zstd")]
+pub fn compress_frame(frame: &[u8]) -> Vec<u8> {
+  // Compressing from a slice into a Vec can't fail
+  zstd::encode_all(frame, zstd::DEFAULT_COMPRESSION_LEVEL).unwrap()
+}
+
+/// Decompresses a frame, failing if it would be larger than `max_size` bytes
+/// so that a small frame can't expand to fill the memory.
+#[cfg(feature = "zstd")]
+pub fn decompress_frame(bytes: &[u8], max_size: usize) -> Result<Vec<u8>, FrameError> {
+  use std::io::Read;
+
+  let decoder = zstd::stream::Decoder::new(bytes).map_err(FrameError::Decompress)?;
+  let mut frame = Vec::new();
+  decoder.take(max_size as u64 + 1)
+    .read_to_end(&mut frame)
+    .map_err(FrameError::Decompress)?;
+  if frame.len() > max_size {
+    return Err(FrameError::TooLarge(max_size));
+  }
+  Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Request, RequestPayload};
+
+  #[test]
+  fn round_trips_frames() {
+    let attachments: [&[u8]; 3] = [b"first", b"", &[0, 1, 2, 255]];
+    let bytes = encode_frame("{}", &attachments);
+    let frame = decode_frame(&bytes).unwrap();
+    assert_eq!(frame.header, "{}");
+    assert_eq!(frame.attachments, attachments);
+  }
+
+  #[test]
+  fn round_trips_frames_without_attachments() {
+    let bytes = encode_frame::<&[u8]>("{\"a\":1}", &[]);
+    let frame = decode_frame(&bytes).unwrap();
+    assert_eq!(frame.header, "{\"a\":1}");
+    assert!(frame.attachments.is_empty());
+  }
+
+  #[test]
+  fn rejects_truncated_frames() {
+    let bytes = encode_frame("{}", &[b"attachment"]);
+    for len in [0, 3, 5, bytes.len() - 1] {
+      assert!(matches!(decode_frame(&bytes[..len]), Err(FrameError::Truncated)), "length {}", len);
+    }
+  }
+
+  #[test]
+  fn rejects_attachment_lengths_past_the_end() {
+    let mut bytes = encode_frame::<&[u8]>("{}", &[]);
+    bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+    bytes.extend_from_slice(b"short");
+    assert!(matches!(decode_frame(&bytes), Err(FrameError::Truncated)));
+  }
+
+  #[test]
+  fn rejects_headers_that_are_not_utf8() {
+    let bytes = [1, 0, 0, 0, 0xff];
+    assert!(matches!(decode_frame(&bytes), Err(FrameError::InvalidHeader)));
+  }
+
+  #[test]
+  fn attachment_indices_refer_to_the_frame() {
+    let header = r#"{"request_id":"1","type":"reload_fxrs","fxrs":[{"attachment":0},{"attachment":1}]}"#;
+    let bytes = encode_frame(header, &[b"fxr"]);
+    let frame = decode_frame(&bytes).unwrap();
+    let request: Request = serde_json::from_str(frame.header).unwrap();
+    let RequestPayload::ReloadFxrs { fxrs } = request.payload else { panic!("not reload_fxrs") };
+    let resolved: Vec<_> = fxrs.iter()
+      .map(|fxr| match fxr {
+        FxrData::Attachment { attachment } => frame.attachments.get(*attachment as usize).copied(),
+        FxrData::Base64(_) => None,
+      })
+      .collect();
+    // The second attachment is missing from the frame
+    assert_eq!(resolved, [Some(&b"fxr"[..]), None]);
+  }
+
+  #[cfg(feature = "zstd")]
+  #[test]
+  fn round_trips_compressed_frames() {
+    let bytes = encode_frame("{}", &[vec![7; 1000]]);
+    assert_eq!(decompress_frame(&compress_frame(&bytes), bytes.len()).unwrap(), bytes);
+  }
+
+  #[cfg(feature = "zstd")]
+  #[test]
+  fn rejects_frames_that_decompress_past_the_limit() {
+    let bytes = encode_frame("{}", &[vec![0; 1 << 20]]);
+    let compressed = compress_frame(&bytes);
+    assert!(compressed.len() < 1024);
+    assert!(matches!(decompress_frame(&compressed, 1024), Err(FrameError::TooLarge(1024))));
+  }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use crate::SupportedFeatures;
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+/// Version of the protocol spoken by this crate. It is increased whenever
+/// requests or responses change in a way clients may need to know about.
+/// Servers that don't send a protocol version predate `get_capabilities`.
+///
+/// Version 2 added error codes, events, authentication, cancellation and the
+/// other transports. Clients should still check [`Capabilities::requests`]
+/// and [`Capabilities::features`] before using a specific request or
+/// feature, since not every server supports all of them.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The JSON Schema of requests, written by the `generate` example.
+pub(crate) const REQUEST_SCHEMA: &str = include_str!("../schema/request.schema.json");
+
+/// Names of the fine-grained features a server can list in
+/// [`Capabilities::features`]. Clients should ignore names they don't know.
+pub mod feature {
+  /// FXRs can be reloaded with `reload_fxrs`.
+  pub const RELOAD: &str = "reload";
+  /// Effects can be respawned with `set_resident_sfx` and `set_sp_effect_sfx`.
+  pub const PARAMS: &str = "params";
+  /// Loaded FXRs can be listed and extracted with `list_fxrs` and `get_fxr`.
+  pub const EXTRACT: &str = "extract";
+  /// Multiple FXRs can be extracted at once with `get_fxrs`.
+  pub const BATCH_EXTRACT: &str = "batch_extract";
+  /// Requests can be sent in binary frames.
+  pub const BINARY_FRAMES: &str = "binary_frames";
+  /// Binary frames can be compressed with zstd. Whether they are depends on
+  /// [`Capabilities::negotiated`]. Text frames are never compressed.
+  pub const ZSTD: &str = "zstd";
+  /// Requests that don't modify the game are handled concurrently, so their
+  /// responses may arrive out of order.
+  pub const CONCURRENT_REQUESTS: &str = "concurrent_requests";
+  /// Clients can subscribe to events with `subscribe`.
+  pub const EVENTS: &str = "events";
+}
+
+/// Sent back by `get_capabilities`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct Capabilities {
+  pub protocol_version: u32,
+  /// The request types the server supports for the current game.
+  pub requests: Vec<RequestType>,
+  /// The names of the features the server supports.
+  pub features: Vec<String>,
+  /// The names of the features in `features` that are enabled for this
+  /// connection, for features like `zstd` that have to be negotiated when
+  /// connecting. Not sent by servers with a protocol version below 2.
+  #[serde(default)]
+  pub negotiated: Vec<String>,
+}
+
+impl Capabilities {
+  pub fn supports_request(&self, type_name: &str) -> bool {
+    self.requests.iter().any(|request| request.name == type_name)
+  }
+
+  pub fn has_feature(&self, name: &str) -> bool {
+    self.features.iter().any(|feature| feature == name)
+  }
+
+  pub fn is_negotiated(&self, name: &str) -> bool {
+    self.negotiated.iter().any(|feature| feature == name)
+  }
+}
+
+/// A request type and the parameters it accepts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct RequestType {
+  /// The value of the `type` property for this request.
+  pub name: String,
+  /// JSON Schema of the request's `type` and parameters. `request_id` is not
+  /// included.
+  #[cfg_attr(feature = "typescript", ts(type = "Record<string, unknown>"))]
+  pub schema: serde_json::Value,
+}
+
+impl SupportedFeatures {
+  /// Whether a request type can be used with a game that has these features.
+  pub fn supports_request(&self, type_name: &str) -> bool {
+    match type_name {
+      "reload_fxrs" => self.reload,
+      "set_resident_sfx" | "set_sp_effect_sfx" => self.params,
+      "get_fxr" | "get_fxrs" | "list_fxrs" => self.extract,
+      _ => true,
+    }
+  }
+
+  /// The names from [`feature`] that correspond to these features.
+  pub fn feature_names(&self) -> Vec<String> {
+    let mut names = Vec::new();
+    if self.reload {
+      names.push(feature::RELOAD.to_string());
+    }
+    if self.params {
+      names.push(feature::PARAMS.to_string());
+    }
+    if self.extract {
+      names.push(feature::EXTRACT.to_string());
+      names.push(feature::BATCH_EXTRACT.to_string());
+    }
+    names
+  }
+}
+
+/// Lists every request type along with the JSON Schema of its parameters.
+/// The schemas are taken from the generated schema file, so that they are
+/// available without the `schema` feature.
+pub fn request_types() -> Vec<RequestType> {
+  // The file is generated from `Request`, so it is always valid JSON
+  let root: serde_json::Value = serde_json::from_str(REQUEST_SCHEMA).unwrap();
+  let variants = root["oneOf"].as_array().map(Vec::as_slice).unwrap_or_default();
+  crate::REQUEST_TYPE_NAMES.iter()
+    .map(|&name| {
+      let mut schema = variants.iter()
+        .find(|schema| schema["properties"]["type"]["enum"][0] == name)
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!({}));
+      // Types like FXR data and batch requests refer to the definitions of
+      // the root schema
+      if schema.to_string().contains("#/definitions/") {
+        schema["definitions"] = root["definitions"].clone();
+      }
+      RequestType { name: name.to_string(), schema }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn every_request_type_has_a_schema() {
+    for request_type in request_types() {
+      assert_eq!(request_type.schema["properties"]["type"]["enum"][0], request_type.name.as_str());
+    }
+  }
+
+  #[cfg(feature = "schema")]
+  #[test]
+  fn request_schema_is_up_to_date() {
+    let schema = serde_json::to_value(schemars::schema_for!(crate::Request)).unwrap();
+    assert_eq!(
+      serde_json::from_str::<serde_json::Value>(REQUEST_SCHEMA).unwrap(),
+      schema,
+      "Run the generate example to update schema/request.schema.json",
+    );
+  }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+/// Written by the server to a file in [`discovery_dir`] named after the
+/// game's process ID once it is listening, so that clients can find it. The
+/// file is removed when the server is shut down with a `shutdown` request,
+/// but is left behind if the game closes or crashes first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct Discovery {
+  /// The ID of the game's process.
+  pub pid: u32,
+  /// The URL of the WebSocket server.
+  pub url: String,
+  pub port: u16,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub game: Option<String>,
+  /// The version of the reloader.
+  pub version: String,
+  /// When the server started, in seconds since the Unix epoch.
+  pub started_at: u64,
+}
+
+impl Discovery {
+  /// The path of the discovery file for a process.
+  pub fn path(pid: u32) -> PathBuf {
+    discovery_dir().join(format!("{}.json", pid))
+  }
+
+  /// Reads every discovery file, newest first. Files that can't be read are
+  /// skipped.
+  pub fn read_all() -> Vec<Discovery> {
+    let Ok(entries) = std::fs::read_dir(discovery_dir()) else {
+      return Vec::new();
+    };
+    let mut servers: Vec<Discovery> = entries
+      .filter_map(|entry| std::fs::read(entry.ok()?.path()).ok())
+      .filter_map(|json| serde_json::from_slice(&json).ok())
+      .collect();
+    servers.sort_by_key(|server| std::cmp::Reverse(server.started_at));
+    servers
+  }
+}
+
+/// The directory discovery files are written to, in the temp directory.
+pub fn discovery_dir() -> PathBuf {
+  std::env::temp_dir().join("fxr-reloader")
+}
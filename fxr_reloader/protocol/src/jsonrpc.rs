@@ -0,0 +1,244 @@
+//! A JSON-RPC 2.0 dialect of the protocol, used by connections to
+//! [`JSONRPC_PATH`].
+//!
+//! The `method` of a JSON-RPC request is the request type, and its `params`
+//! are the request's parameters. Responses put the message and data of a
+//! successful [`Response`] in `result`, and errors in the standard error
+//! object with the [`ResponseError`] as its `data`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::{ErrorCode, Response, ResponseData, ResponseError};
+
+/// The URL path clients connect to in order to use JSON-RPC.
+pub const JSONRPC_PATH: &str = "/jsonrpc";
+
+pub const RPC_PARSE_ERROR: i32 = -32700;
+pub const RPC_INVALID_REQUEST: i32 = -32600;
+pub const RPC_METHOD_NOT_FOUND: i32 = -32601;
+pub const RPC_INVALID_PARAMS: i32 = -32602;
+pub const RPC_INTERNAL_ERROR: i32 = -32603;
+/// Used for errors that don't map to one of the codes reserved by JSON-RPC.
+pub const RPC_SERVER_ERROR: i32 = -32000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+  pub jsonrpc: String,
+  pub method: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub params: Option<Value>,
+  /// Requests without an ID are notifications, which are not responded to.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<Value>,
+}
+
+impl RpcRequest {
+  /// Converts the request to a regular request as a JSON value. The JSON of
+  /// the ID is used as the request ID, so that [`RpcResponse::from`] can turn
+  /// it back into the original ID.
+  pub fn to_request_value(&self) -> Result<Value, RpcError> {
+    if self.jsonrpc != "2.0" {
+      return Err(RpcError::new(RPC_INVALID_REQUEST, "Unsupported JSON-RPC version."));
+    }
+    let mut request = match &self.params {
+      None => Map::new(),
+      Some(Value::Object(params)) => params.clone(),
+      Some(_) => return Err(RpcError::new(RPC_INVALID_PARAMS, "Parameters must be given by name.")),
+    };
+    let id = self.id.as_ref().unwrap_or(&Value::Null);
+    request.insert("request_id".to_string(), Value::String(id.to_string()));
+    // The request to cancel is given by its JSON-RPC ID, so it is converted
+    // the same way
+    if self.method == "cancel" {
+      if let Some(target) = request.get_mut("target_request_id") {
+        *target = Value::String(target.to_string());
+      }
+    }
+    request.insert("type".to_string(), Value::String(self.method.clone()));
+    Ok(Value::Object(request))
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResponse {
+  pub jsonrpc: String,
+  pub id: Value,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub result: Option<RpcResult>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+  pub fn error(id: Value, error: RpcError) -> Self {
+    Self {
+      jsonrpc: "2.0".to_string(),
+      id,
+      result: None,
+      error: Some(error),
+    }
+  }
+}
+
+impl From<Response> for RpcResponse {
+  fn from(response: Response) -> Self {
+    let id = serde_json::from_str(&response.request_id).unwrap_or(Value::Null);
+    if response.success {
+      return Self {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: Some(RpcResult {
+          message: response.message,
+          data: response.data,
+        }),
+        error: None,
+      };
+    }
+    let code = response.error.as_ref().map_or(RPC_SERVER_ERROR, |error| error.code.rpc_code());
+    Self::error(id, RpcError {
+      code,
+      message: response.message,
+      data: response.error,
+    })
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResult {
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub data: Option<ResponseData>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+  pub code: i32,
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub data: Option<ResponseError>,
+}
+
+impl RpcError {
+  pub fn new(code: i32, message: impl Into<String>) -> Self {
+    Self {
+      code,
+      message: message.into(),
+      data: None,
+    }
+  }
+}
+
+/// A message sent by the server on its own, like `server_info`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcNotification {
+  pub jsonrpc: String,
+  pub method: String,
+  pub params: Value,
+}
+
+impl RpcNotification {
+  pub fn new(method: impl Into<String>, params: impl Serialize) -> Self {
+    Self {
+      jsonrpc: "2.0".to_string(),
+      method: method.into(),
+      params: serde_json::to_value(params).unwrap_or(Value::Null),
+    }
+  }
+}
+
+impl ErrorCode {
+  /// The JSON-RPC error code used for this error.
+  pub fn rpc_code(self) -> i32 {
+    match self {
+      ErrorCode::InvalidRequest | ErrorCode::InvalidParam | ErrorCode::RequestNotFound => RPC_INVALID_PARAMS,
+      ErrorCode::UnknownRequestType => RPC_METHOD_NOT_FOUND,
+      ErrorCode::InvalidFrame => RPC_INVALID_REQUEST,
+      ErrorCode::Internal => RPC_INTERNAL_ERROR,
+      _ => RPC_SERVER_ERROR,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+  use super::*;
+  use crate::{Request, RequestPayload};
+
+  fn rpc_request(value: Value) -> RpcRequest {
+    serde_json::from_value(value).unwrap()
+  }
+
+  fn to_request(value: Value) -> Request {
+    Request::from_value(rpc_request(value).to_request_value().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn uses_the_json_of_the_id_as_the_request_id() {
+    let request = to_request(json!({ "jsonrpc": "2.0", "method": "get_fxr", "params": { "id": 1 }, "id": 7 }));
+    assert_eq!(request.request_id, "7");
+    assert!(matches!(request.payload, RequestPayload::GetFxr { id: 1 }));
+
+    let request = to_request(json!({ "jsonrpc": "2.0", "method": "list_fxrs", "id": "abc" }));
+    assert_eq!(request.request_id, "\"abc\"");
+  }
+
+  #[test]
+  fn notifications_have_a_null_request_id() {
+    let request = rpc_request(json!({ "jsonrpc": "2.0", "method": "list_fxrs" }));
+    assert_eq!(request.id, None);
+    let request = Request::from_value(request.to_request_value().unwrap()).unwrap();
+    assert_eq!(request.request_id, "null");
+  }
+
+  #[test]
+  fn converts_cancel_targets_like_ids() {
+    let request = to_request(json!({
+      "jsonrpc": "2.0",
+      "method": "cancel",
+      "params": { "target_request_id": "slow" },
+      "id": 2,
+    }));
+    let RequestPayload::Cancel { target_request_id } = request.payload else { panic!("not cancel") };
+    assert_eq!(target_request_id, "\"slow\"");
+  }
+
+  #[test]
+  fn rejects_invalid_requests() {
+    let error = rpc_request(json!({ "jsonrpc": "1.0", "method": "list_fxrs", "id": 1 })).to_request_value().unwrap_err();
+    assert_eq!(error.code, RPC_INVALID_REQUEST);
+    let error = rpc_request(json!({ "jsonrpc": "2.0", "method": "get_fxrs", "params": [[1]], "id": 1 }))
+      .to_request_value()
+      .unwrap_err();
+    assert_eq!(error.code, RPC_INVALID_PARAMS);
+  }
+
+  #[test]
+  fn responses_get_the_original_id_back() {
+    let request = to_request(json!({ "jsonrpc": "2.0", "method": "list_fxrs", "id": "abc" }));
+    let response = RpcResponse::from(Response {
+      request_id: request.request_id,
+      success: true,
+      message: "ok".to_string(),
+      data: None,
+      error: None,
+    });
+    assert_eq!(response.id, json!("abc"));
+    assert_eq!(response.result.unwrap().message, "ok");
+  }
+
+  #[test]
+  fn error_responses_use_rpc_codes() {
+    let response = RpcResponse::from(Response {
+      request_id: "1".to_string(),
+      success: false,
+      message: "Unknown request type".to_string(),
+      data: None,
+      error: Some(ResponseError::new(ErrorCode::UnknownRequestType)),
+    });
+    assert_eq!(response.id, json!(1));
+    let error = response.error.unwrap();
+    assert_eq!(error.code, RPC_METHOD_NOT_FOUND);
+    assert_eq!(error.data.unwrap().code, ErrorCode::UnknownRequestType);
+  }
+}
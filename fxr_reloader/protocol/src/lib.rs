@@ -1,6 +1,26 @@
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+mod auth;
+mod binary;
+mod capabilities;
+mod discovery;
+mod jsonrpc;
+mod parse;
+mod request;
+mod response;
+mod stats;
+
+pub use auth::*;
+pub use binary::*;
+pub use capabilities::*;
+pub use discovery::*;
+pub use jsonrpc::*;
+pub use parse::*;
+pub use request::*;
+pub use response::*;
+pub use stats::*;
+
 #[derive(Debug, Error, Serialize, Deserialize)]
 pub enum FxrManagerError {
   #[error("Could not locate CSSfx instance.")]
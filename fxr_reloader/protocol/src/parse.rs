@@ -0,0 +1,363 @@
+//! Parsing of requests that reports which param was invalid.
+//!
+//! `RequestPayload` is tagged by its `type` property, which serde can only
+//! deserialize by buffering the params first, losing track of where an error
+//! happened. To find the invalid param, requests are rewritten with the type
+//! as the key of their params and parsed with [`TracedPayload`], which mirrors
+//! `RequestPayload` in that shape. They are parsed from a `serde_value::Value`
+//! rather than JSON so that errors about missing params say which one is
+//! missing without having to parse the error message.
+//!
+//! Running `serde_path_to_error` on `RequestPayload` itself only ever reports
+//! an empty path, so the second enum can't be avoided. The remote derive makes
+//! the compiler check that its variants have the same fields, and the tests
+//! check the rest against the generated schema: that every request type is
+//! there, and that params are named and typed the same way.
+
+use std::fmt;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use serde_path_to_error::Segment;
+use serde_value::DeserializerError;
+use crate::{EventTopic, FxrData, LogLevel, Request, RequestPayload};
+
+/// Why a request couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+  /// The path of the param that was missing or invalid, like `fxrs` or
+  /// `requests[1].id`. `None` if the request as a whole was invalid.
+  pub param: Option<String>,
+  pub error: serde_json::Error,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.param {
+      Some(param) => write!(f, "{}: {}", param, self.error),
+      None => self.error.fmt(f),
+    }
+  }
+}
+
+impl std::error::Error for ParseError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    Some(&self.error)
+  }
+}
+
+impl ParseError {
+  fn param(param: &str, error: serde_json::Error) -> Self {
+    Self { param: Some(param.to_string()), error }
+  }
+}
+
+impl Request {
+  /// Parses a request from JSON. Unlike deserializing it directly, errors
+  /// say which param was missing or invalid.
+  pub fn from_value(value: Value) -> Result<Self, ParseError> {
+    let Value::Object(mut object) = value else {
+      return Err(ParseError { param: None, error: serde_json::Error::custom("a request must be an object") });
+    };
+    let request_id = match object.remove("request_id") {
+      Some(request_id) => String::deserialize(request_id).map_err(|e| ParseError::param("request_id", e))?,
+      None => return Err(ParseError::param("request_id", serde_json::Error::missing_field("request_id"))),
+    };
+    let timeout_ms = match object.remove("timeout_ms") {
+      Some(timeout_ms) => Option::<u64>::deserialize(timeout_ms).map_err(|e| ParseError::param("timeout_ms", e))?,
+      None => None,
+    };
+    let payload = payload_from_object(object)?;
+    Ok(Request { request_id, timeout_ms, payload })
+  }
+}
+
+impl RequestPayload {
+  /// Parses a request type and its params from JSON, saying which param was
+  /// missing or invalid if it fails.
+  pub fn from_value(value: Value) -> Result<Self, ParseError> {
+    match value {
+      Value::Object(object) => payload_from_object(object),
+      _ => Err(ParseError { param: None, error: serde_json::Error::custom("a request must be an object") }),
+    }
+  }
+}
+
+fn payload_from_object(object: Map<String, Value>) -> Result<RequestPayload, ParseError> {
+  let externally_tagged = externally_tagged(object).map_err(|error| ParseError::param("type", error))?;
+  // Any JSON value can be converted
+  let value = serde_value::to_value(externally_tagged).unwrap();
+  let mut track = serde_path_to_error::Track::new();
+  let deserializer = serde_path_to_error::Deserializer::new(value, &mut track);
+  TracedPayload::deserialize(deserializer).map_err(|error| {
+    let mut param = param_path(track.path().iter());
+    if let DeserializerError::MissingField(field) = error {
+      if !param.is_empty() {
+        param.push('.');
+      }
+      param.push_str(field);
+    }
+    ParseError { param: (!param.is_empty()).then_some(param), error: error.into_error() }
+  })
+}
+
+/// Rewrites `{ "type": "get_fxr", "id": 1 }` as `{ "get_fxr": { "id": 1 } }`,
+/// including the requests in batches.
+fn externally_tagged(mut object: Map<String, Value>) -> Result<Value, serde_json::Error> {
+  let request_type = match object.remove("type") {
+    Some(Value::String(request_type)) => request_type,
+    Some(other) => return Err(serde_json::Error::invalid_type(unexpected(&other), &"a request type")),
+    None => return Err(serde_json::Error::missing_field("type")),
+  };
+  if let Some(Value::Array(requests)) = object.get_mut("requests") {
+    for request in requests {
+      // Steps without a valid type are left for `TracedPayload` to reject
+      if let Value::Object(step) = request {
+        if step.get("type").is_some_and(Value::is_string) {
+          *request = externally_tagged(std::mem::take(step))?;
+        }
+      }
+    }
+  }
+  Ok(Value::Object(Map::from_iter([(request_type, Value::Object(object))])))
+}
+
+fn unexpected(value: &Value) -> serde::de::Unexpected<'_> {
+  use serde::de::Unexpected;
+  match value {
+    Value::Null => Unexpected::Unit,
+    Value::Bool(b) => Unexpected::Bool(*b),
+    Value::Number(_) => Unexpected::Other("number"),
+    Value::String(s) => Unexpected::Str(s),
+    Value::Array(_) => Unexpected::Seq,
+    Value::Object(_) => Unexpected::Map,
+  }
+}
+
+/// Formats the path of an error like `requests[1].id`, leaving out the type
+/// keys added by `externally_tagged`.
+fn param_path<'a>(segments: impl Iterator<Item = &'a Segment>) -> String {
+  let mut path = String::new();
+  let mut is_type = true;
+  let mut last_key = "";
+  for segment in segments {
+    match segment {
+      _ if is_type => is_type = false,
+      Segment::Seq { index } => {
+        path.push_str(&format!("[{}]", index));
+        // The steps of a batch are the only lists of requests
+        is_type = last_key == "requests";
+      }
+      Segment::Map { key } => {
+        if !path.is_empty() {
+          path.push('.');
+        }
+        path.push_str(key);
+        last_key = key;
+      }
+      Segment::Enum { .. } | Segment::Unknown => {}
+    }
+  }
+  path
+}
+
+/// `RequestPayload` with the request type as an external tag. Unit variants
+/// are written with braces so that they accept, and ignore, params, just like
+/// `RequestPayload` does.
+#[derive(Deserialize)]
+#[serde(remote = "RequestPayload", rename_all = "snake_case")]
+enum TracedPayload {
+  ReloadFxrs {
+    fxrs: Vec<FxrData>,
+  },
+  SetResidentSfx {
+    weapon: u32,
+    sfx: i32,
+    dmy: i32,
+  },
+  SetSpEffectSfx {
+    #[serde(rename = "spEffect")]
+    sp_effect: u32,
+    sfx: i32,
+    dmy: i16,
+    vfx: Option<i32>,
+  },
+  GetFxr {
+    id: u32,
+  },
+  GetFxrs {
+    ids: Vec<u32>,
+  },
+  ListFxrs {},
+  GetCapabilities {},
+  Batch {
+    #[serde(deserialize_with = "traced_steps")]
+    requests: Vec<RequestPayload>,
+    #[serde(alias = "transactional")]
+    stop_on_error: Option<bool>,
+  },
+  Subscribe {
+    events: Vec<EventTopic>,
+  },
+  SubscribeLogs {
+    level: Option<LogLevel>,
+  },
+  Unsubscribe {
+    events: Vec<EventTopic>,
+  },
+  Cancel {
+    target_request_id: String,
+  },
+  Auth {
+    token: String,
+  },
+  GetStats {},
+  Shutdown {},
+}
+
+#[derive(Deserialize)]
+struct TracedStep(#[serde(with = "TracedPayload")] RequestPayload);
+
+fn traced_steps<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<RequestPayload>, D::Error> {
+  let steps = Vec::<TracedStep>::deserialize(deserializer)?;
+  Ok(steps.into_iter().map(|step| step.0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+  use super::*;
+  use crate::capabilities::REQUEST_SCHEMA;
+  use crate::REQUEST_TYPE_NAMES;
+
+  /// Makes up a valid value for a JSON Schema from the generated schema.
+  fn sample(schema: &Value, definitions: &Value) -> Value {
+    if let Some(reference) = schema["$ref"].as_str() {
+      return sample(&definitions[reference.trim_start_matches("#/definitions/")], definitions);
+    }
+    if let Some(value) = schema["enum"].get(0) {
+      return value.clone();
+    }
+    if let Some(first) = schema["oneOf"].get(0).or_else(|| schema["anyOf"].get(0)) {
+      return sample(first, definitions);
+    }
+    let schema_type = match &schema["type"] {
+      Value::Array(types) => types.iter().find(|t| *t != "null").unwrap(),
+      schema_type => schema_type,
+    };
+    match schema_type.as_str().unwrap() {
+      "integer" | "number" => json!(1),
+      "string" => json!("x"),
+      "boolean" => json!(true),
+      "array" => json!([sample(&schema["items"], definitions)]),
+      "object" => {
+        let properties = schema["properties"].as_object().unwrap();
+        Value::Object(properties.iter().map(|(name, property)| (name.clone(), sample(property, definitions))).collect())
+      }
+      other => panic!("unexpected schema type {}", other),
+    }
+  }
+
+  /// Every request type as described by the generated schema, with every
+  /// param set.
+  fn sample_requests() -> Vec<Value> {
+    let root: Value = serde_json::from_str(REQUEST_SCHEMA).unwrap();
+    root["oneOf"].as_array().unwrap().iter()
+      .map(|variant| {
+        let mut request = sample(variant, &root["definitions"]);
+        request["request_id"] = json!("1");
+        request
+      })
+      .collect()
+  }
+
+  fn param(value: Value) -> Option<String> {
+    Request::from_value(value).unwrap_err().param
+  }
+
+  #[test]
+  fn parses_every_request_type() {
+    for name in REQUEST_TYPE_NAMES {
+      let error = Request::from_value(json!({ "request_id": "1", "type": name })).err();
+      assert!(
+        error.as_ref().is_none_or(|error| !error.error.to_string().contains("unknown variant")),
+        "{} is missing from TracedPayload: {:?}", name, error,
+      );
+    }
+  }
+
+  #[test]
+  fn traced_payload_matches_request_payload() {
+    for request in sample_requests() {
+      let traced = Request::from_value(request.clone()).unwrap_or_else(|e| panic!("{}: {}", request, e));
+      let derived: Request = serde_json::from_value(request.clone()).unwrap();
+      assert_eq!(serde_json::to_value(traced).unwrap(), serde_json::to_value(derived).unwrap(), "{}", request);
+    }
+  }
+
+  #[test]
+  fn reports_every_missing_param() {
+    let root: Value = serde_json::from_str(REQUEST_SCHEMA).unwrap();
+    for (variant, request) in root["oneOf"].as_array().unwrap().iter().zip(sample_requests()) {
+      for required in variant["required"].as_array().unwrap().iter().filter_map(Value::as_str) {
+        if required == "type" {
+          continue;
+        }
+        let mut request = request.clone();
+        request.as_object_mut().unwrap().remove(required);
+        assert_eq!(param(request.clone()).as_deref(), Some(required), "{}", request);
+      }
+    }
+  }
+
+  #[test]
+  fn parses_the_same_as_deserialize() {
+    let value = json!({
+      "request_id": "1",
+      "timeout_ms": 500,
+      "type": "batch",
+      "requests": [
+        { "type": "get_fxr", "id": 402030 },
+        { "type": "set_sp_effect_sfx", "spEffect": 1, "sfx": 2, "dmy": 3 },
+        { "type": "list_fxrs", "ignored": true },
+      ],
+    });
+    let traced = Request::from_value(value.clone()).unwrap();
+    let derived: Request = serde_json::from_value(value).unwrap();
+    assert_eq!(serde_json::to_value(traced).unwrap(), serde_json::to_value(derived).unwrap());
+  }
+
+  #[test]
+  fn accepts_the_old_name_of_stop_on_error() {
+    let request = Request::from_value(json!({ "request_id": "1", "type": "batch", "requests": [], "transactional": true }));
+    assert!(matches!(request.unwrap().payload, RequestPayload::Batch { stop_on_error: Some(true), .. }));
+  }
+
+  #[test]
+  fn reports_missing_params() {
+    assert_eq!(param(json!({ "request_id": "1", "type": "reload_fxrs" })).as_deref(), Some("fxrs"));
+    assert_eq!(param(json!({ "type": "list_fxrs" })).as_deref(), Some("request_id"));
+  }
+
+  #[test]
+  fn reports_params_with_the_wrong_type() {
+    assert_eq!(param(json!({ "request_id": "1", "type": "reload_fxrs", "fxrs": 5 })).as_deref(), Some("fxrs"));
+    assert_eq!(param(json!({ "request_id": 1, "type": "list_fxrs" })).as_deref(), Some("request_id"));
+    assert_eq!(param(json!({ "request_id": "1", "type": "get_fxrs", "ids": [1, "2"] })).as_deref(), Some("ids[1]"));
+  }
+
+  #[test]
+  fn reports_params_of_batch_steps() {
+    let value = json!({
+      "request_id": "1",
+      "type": "batch",
+      "requests": [{ "type": "list_fxrs" }, { "type": "get_fxr", "id": "x" }],
+    });
+    assert_eq!(param(value).as_deref(), Some("requests[1].id"));
+  }
+
+  #[test]
+  fn reports_unknown_types_without_a_param() {
+    assert_eq!(param(json!({ "request_id": "1", "type": "explode" })), None);
+  }
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use crate::{EventTopic, FxrData, LogLevel};
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+/// A request sent from a client to the reloader server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct Request {
+  /// Echoed back in the response so the client can tell which request it
+  /// belongs to.
+  pub request_id: String,
+  /// How long the server may take to handle the request, in milliseconds,
+  /// including time spent waiting for earlier requests. If it takes longer,
+  /// the request is stopped and fails with a `timed_out` error.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub timeout_ms: Option<u64>,
+  #[serde(flatten)]
+  pub payload: RequestPayload,
+}
+
+/// The request type and its parameters, tagged by the `type` property.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum RequestPayload {
+  /// Patches the definitions of the given FXRs so that new instances use
+  /// them.
+  ReloadFxrs {
+    fxrs: Vec<FxrData>,
+  },
+  /// Sets the resident SFX of a weapon, respawning the effect.
+  SetResidentSfx {
+    weapon: u32,
+    sfx: i32,
+    dmy: i32,
+  },
+  /// Sets the SFX of a SpEffect's VFX, respawning the effect.
+  SetSpEffectSfx {
+    #[serde(rename = "spEffect")]
+    sp_effect: u32,
+    sfx: i32,
+    dmy: i16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    vfx: Option<i32>,
+  },
+  /// Extracts a loaded FXR from the game's memory.
+  GetFxr {
+    id: u32,
+  },
+  /// Extracts multiple loaded FXRs from the game's memory.
+  GetFxrs {
+    ids: Vec<u32>,
+  },
+  /// Lists the IDs of all loaded FXRs.
+  ListFxrs,
+  /// Gets the protocol version, supported request types and features of the
+  /// server.
+  GetCapabilities,
+  /// Runs multiple requests in order and responds with all of their results.
+  Batch {
+    requests: Vec<RequestPayload>,
+    /// Check that every request is supported before running any of them, and
+    /// stop at the first one that fails. Requests that already ran are not
+    /// undone. `transactional` is accepted as an alias, for older clients.
+    #[serde(alias = "transactional", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    stop_on_error: Option<bool>,
+  },
+  /// Starts sending events of the given kinds to the client.
+  Subscribe {
+    events: Vec<EventTopic>,
+  },
+  /// Starts sending the server's log records to the client as `log` events.
+  /// Stop with an `unsubscribe` request for `logs`.
+  SubscribeLogs {
+    /// The lowest level to send. Defaults to `info`. Records below the
+    /// server's own `log_level` are never sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    level: Option<LogLevel>,
+  },
+  /// Stops sending events of the given kinds to the client.
+  Unsubscribe {
+    events: Vec<EventTopic>,
+  },
+  /// Stops a request that is waiting to be handled or still being handled,
+  /// which then fails with a `cancelled` error.
+  Cancel {
+    /// The ID of the request to cancel.
+    target_request_id: String,
+  },
+  /// Authenticates the client with one of the tokens in the server's config.
+  /// Can't be used in a batch.
+  Auth {
+    token: String,
+  },
+  /// Gets counters and request latencies collected since the server started.
+  GetStats,
+  /// Closes every connection and stops the server once the requests that are
+  /// being handled have finished. Requires the `read_write` role. Can't be
+  /// used in a batch.
+  Shutdown,
+}
+
+pub const REQUEST_TYPE_NAMES: &[&str] = &[
+  "reload_fxrs",
+  "set_resident_sfx",
+  "set_sp_effect_sfx",
+  "get_fxr",
+  "get_fxrs",
+  "list_fxrs",
+  "get_capabilities",
+  "batch",
+  "subscribe",
+  "subscribe_logs",
+  "unsubscribe",
+  "cancel",
+  "auth",
+  "get_stats",
+  "shutdown",
+];
+
+impl RequestPayload {
+  /// The value of the `type` property for this request.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      RequestPayload::ReloadFxrs { .. } => "reload_fxrs",
+      RequestPayload::SetResidentSfx { .. } => "set_resident_sfx",
+      RequestPayload::SetSpEffectSfx { .. } => "set_sp_effect_sfx",
+      RequestPayload::GetFxr { .. } => "get_fxr",
+      RequestPayload::GetFxrs { .. } => "get_fxrs",
+      RequestPayload::ListFxrs => "list_fxrs",
+      RequestPayload::GetCapabilities => "get_capabilities",
+      RequestPayload::Batch { .. } => "batch",
+      RequestPayload::Subscribe { .. } => "subscribe",
+      RequestPayload::SubscribeLogs { .. } => "subscribe_logs",
+      RequestPayload::Unsubscribe { .. } => "unsubscribe",
+      RequestPayload::Cancel { .. } => "cancel",
+      RequestPayload::Auth { .. } => "auth",
+      RequestPayload::GetStats => "get_stats",
+      RequestPayload::Shutdown => "shutdown",
+    }
+  }
+
+  /// Whether the request modifies the game's state. These are handled in the
+  /// order they were received instead of concurrently.
+  pub fn is_mutating(&self) -> bool {
+    match self {
+      RequestPayload::Batch { requests, .. } => requests.iter().any(RequestPayload::is_mutating),
+      _ => matches!(
+        self,
+        RequestPayload::ReloadFxrs { .. }
+          | RequestPayload::SetResidentSfx { .. }
+          | RequestPayload::SetSpEffectSfx { .. }
+      ),
+    }
+  }
+}
@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize};
+use crate::{Capabilities, FxrData, FxrManagerError, Role, Stats};
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+/// The features a game supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct SupportedFeatures {
+  pub reload: bool,
+  pub params: bool,
+  pub extract: bool,
+}
+
+/// The response to a request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct Response {
+  pub request_id: String,
+  pub success: bool,
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub data: Option<ResponseData>,
+  /// Set if the request failed.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub error: Option<ResponseError>,
+}
+
+/// Stable codes for why a request failed, so clients don't have to match on
+/// the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum ErrorCode {
+  /// The request is not valid JSON or does not have the right parameters.
+  InvalidRequest,
+  UnknownRequestType,
+  /// A binary frame could not be decoded.
+  InvalidFrame,
+  /// A parameter has an invalid value, like FXR data that isn't valid base64.
+  InvalidParam,
+  /// The request is not supported in the current game.
+  UnsupportedOperation,
+  UnsupportedGame,
+  GameDetectionFailed,
+  /// The game's CSSfx instance could not be found, usually because the game
+  /// has not finished loading yet.
+  CssfxMissing,
+  InvalidFxr,
+  FxrNotFound,
+  Internal,
+  /// The request took longer than its `timeout_ms`.
+  TimedOut,
+  /// The request was stopped by a `cancel` request.
+  Cancelled,
+  /// The request a `cancel` request refers to is not waiting or being handled,
+  /// for example because it has already finished.
+  RequestNotFound,
+  /// The client needs to authenticate before using the request, or gave an
+  /// invalid token.
+  Unauthenticated,
+  /// The client's role doesn't allow using the request.
+  PermissionDenied,
+  /// A code this version of the protocol doesn't know about.
+  #[serde(other)]
+  Unknown,
+}
+
+/// Details about why a request failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct ResponseError {
+  pub code: ErrorCode,
+  /// The ID of the FXR the error is about.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub fxr_id: Option<u32>,
+  /// The name of the request parameter the error is about.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub param: Option<String>,
+  /// The index of the binary frame attachment the error is about.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub attachment: Option<u32>,
+  /// The type of the request that failed, if the server could tell.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub request_type: Option<String>,
+  /// The index of the request in a batch that failed.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub step: Option<u32>,
+}
+
+impl ResponseError {
+  pub fn new(code: ErrorCode) -> Self {
+    Self {
+      code,
+      fxr_id: None,
+      param: None,
+      attachment: None,
+      request_type: None,
+      step: None,
+    }
+  }
+}
+
+impl From<&FxrManagerError> for ResponseError {
+  fn from(e: &FxrManagerError) -> Self {
+    match e {
+      FxrManagerError::CSSfxInstanceMissing => ResponseError::new(ErrorCode::CssfxMissing),
+      FxrManagerError::InvalidFxr => ResponseError::new(ErrorCode::InvalidFxr),
+      FxrManagerError::GameDetectionError(_) => ResponseError::new(ErrorCode::GameDetectionFailed),
+      FxrManagerError::FxrNotFound(id) => ResponseError {
+        fxr_id: Some(*id),
+        ..ResponseError::new(ErrorCode::FxrNotFound)
+      },
+      FxrManagerError::UnsupportedOperation(_) => ResponseError::new(ErrorCode::UnsupportedOperation),
+      FxrManagerError::UnsupportedGame => ResponseError::new(ErrorCode::UnsupportedGame),
+    }
+  }
+}
+
+/// Data sent back by requests that return something.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum ResponseData {
+  /// Sent back by `get_fxr`.
+  Fxr {
+    fxr: FxrData,
+  },
+  /// Sent back by `list_fxrs`. The key differs from `get_fxrs`, so that an
+  /// empty list of FXRs can be told apart from an empty list of IDs.
+  FxrIds {
+    ids: Vec<u32>,
+  },
+  /// Sent back by `get_fxrs`. FXRs in the same order as the requested IDs,
+  /// or null for IDs that could not be found.
+  Fxrs {
+    fxrs: Vec<Option<FxrData>>,
+  },
+  /// Sent back by `get_capabilities`.
+  Capabilities {
+    capabilities: Capabilities,
+  },
+  /// Sent back by `batch`. The results of the requests that were run, in
+  /// order.
+  Batch {
+    results: Vec<BatchResult>,
+  },
+  /// Sent back by `auth`.
+  Auth {
+    role: Role,
+  },
+  /// Sent back by `get_stats`.
+  Stats {
+    stats: Stats,
+  },
+}
+
+/// The result of one of the requests in a batch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct BatchResult {
+  pub success: bool,
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub data: Option<ResponseData>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub error: Option<ResponseError>,
+}
+
+impl From<Response> for BatchResult {
+  fn from(response: Response) -> Self {
+    Self {
+      success: response.success,
+      message: response.message,
+      data: response.data,
+      error: response.error,
+    }
+  }
+}
+
+/// Sent by the server to every client as soon as it connects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct ServerInfo {
+  pub version: String,
+  /// Not sent by servers that predate `get_capabilities`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub protocol_version: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub game: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub features: Option<SupportedFeatures>,
+  /// The ID the server gave this connection. Events caused by other clients
+  /// identify them by this ID.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub client_id: Option<u64>,
+  /// What the client is allowed to do. Not sent by servers that predate
+  /// authentication.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub role: Option<Role>,
+  /// Set if the server failed to detect a supported game.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub error: Option<String>,
+}
+
+/// Kinds of events clients can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum EventTopic {
+  /// `fxrs_changed` events.
+  FxrsChanged,
+  /// `log` events. Subscribing with `subscribe_logs` also picks the lowest
+  /// level that is sent.
+  Logs,
+}
+
+/// How important a log record is, from most to least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum LogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+/// Messages the server sends on its own rather than in response to a
+/// request. These are tagged by the `type` property.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum ServerEvent {
+  ServerInfo(ServerInfo),
+  /// FXRs were loaded or unloaded by the game, for example because the player
+  /// moved to a different area.
+  FxrsChanged {
+    added: Vec<u32>,
+    removed: Vec<u32>,
+  },
+  /// FXRs were reloaded by another client.
+  FxrReloaded {
+    /// The IDs of the reloaded FXRs.
+    ids: Vec<u32>,
+    /// The ID of the client that reloaded them.
+    client: u64,
+  },
+  /// A record the server logged.
+  Log {
+    level: LogLevel,
+    /// When the record was logged, in milliseconds since the Unix epoch.
+    timestamp_ms: u64,
+    message: String,
+    /// The request being handled when the record was logged, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    request_id: Option<String>,
+    /// The client that sent that request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    client: Option<u64>,
+  },
+}
+
+impl ServerEvent {
+  /// The topic clients need to subscribe to to get this event. `None` for
+  /// events that are sent to every client.
+  pub fn topic(&self) -> Option<EventTopic> {
+    match self {
+      ServerEvent::ServerInfo(_) => None,
+      ServerEvent::FxrsChanged { .. } => Some(EventTopic::FxrsChanged),
+      ServerEvent::FxrReloaded { .. } => None,
+      ServerEvent::Log { .. } => Some(EventTopic::Logs),
+    }
+  }
+
+  /// The ID of the client that caused this event, if any. Events are not
+  /// sent back to the client that caused them.
+  pub fn origin(&self) -> Option<u64> {
+    match self {
+      ServerEvent::FxrReloaded { client, .. } => Some(*client),
+      _ => None,
+    }
+  }
+}
+
+/// Any message the server may send to a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub enum ServerMessage {
+  Event(ServerEvent),
+  Response(Response),
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+  use super::*;
+
+  #[test]
+  fn empty_lists_keep_their_variant() {
+    let fxrs: ResponseData = serde_json::from_value(json!({ "fxrs": [] })).unwrap();
+    assert_eq!(fxrs, ResponseData::Fxrs { fxrs: Vec::new() });
+    let ids: ResponseData = serde_json::from_value(json!({ "ids": [] })).unwrap();
+    assert_eq!(ids, ResponseData::FxrIds { ids: Vec::new() });
+  }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::ErrorCode;
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+/// Sent back by `get_stats`. Counters start at zero when the server starts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct Stats {
+  pub uptime_secs: u64,
+  /// Clients that are connected right now.
+  pub connected_clients: u64,
+  /// Connections accepted since the server started.
+  pub total_connections: u64,
+  /// Size of all messages received from clients, in bytes.
+  pub bytes_received: u64,
+  /// Size of all messages sent to clients, in bytes.
+  pub bytes_sent: u64,
+  pub fxrs_reloaded: u64,
+  pub fxrs_extracted: u64,
+  /// Events that were not sent to clients because they couldn't keep up.
+  /// Not sent by servers that predate it.
+  #[serde(default)]
+  pub events_dropped: u64,
+  /// How long it took to find the game's FXR manager, which happens the first
+  /// time a request needs it, in microseconds.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[cfg_attr(feature = "typescript", ts(optional))]
+  pub fxr_manager_resolve_us: Option<u64>,
+  /// Stats for each request type that has been used.
+  pub requests: Vec<RequestStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct RequestStats {
+  pub request_type: String,
+  /// How many requests of this type were handled, including failed ones.
+  pub count: u64,
+  /// How many requests failed with each error code.
+  pub failures: BTreeMap<ErrorCode, u64>,
+  pub latency: LatencyHistogram,
+}
+
+/// How long requests took to handle, in microseconds. `counts` has one more
+/// entry than `bounds_us`: each count is the number of requests that took at most the
+/// bound at the same index and longer than the one before it, and the last
+/// count is the number of requests that took longer than every bound.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[cfg_attr(feature = "typescript", derive(TS))]
+pub struct LatencyHistogram {
+  pub bounds_us: Vec<u64>,
+  pub counts: Vec<u64>,
+  /// The total time spent on requests.
+  pub sum_us: u64,
+}
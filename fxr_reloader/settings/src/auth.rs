@@ -0,0 +1,78 @@
+use protocol::Role;
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+
+/// A token clients can authenticate with, and the role it gives them.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuthToken {
+  pub token: String,
+  pub role: Role,
+}
+
+/// The tokens from the config. If there are none, clients don't need to
+/// authenticate.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct AuthConfig {
+  /// A token that gives clients the `read_write` role.
+  #[serde(default)]
+  pub auth_token: Option<String>,
+  #[serde(default)]
+  pub auth_tokens: Vec<AuthToken>,
+}
+
+impl AuthConfig {
+  pub fn required(&self) -> bool {
+    self.auth_token.is_some() || !self.auth_tokens.is_empty()
+  }
+
+  /// The role of a client before it has authenticated.
+  pub fn initial_role(&self) -> Role {
+    if self.required() { Role::Unauthenticated } else { Role::ReadWrite }
+  }
+
+  /// The role a token gives, or `None` if the token is not valid.
+  pub fn role_for_token(&self, token: &str) -> Option<Role> {
+    if !self.required() {
+      return Some(Role::ReadWrite);
+    }
+    self.auth_token.iter()
+      .map(|valid| (valid.as_str(), Role::ReadWrite))
+      .chain(self.auth_tokens.iter().map(|valid| (valid.token.as_str(), valid.role)))
+      .find(|(valid, _)| tokens_match(valid, token))
+      .map(|(_, role)| role)
+  }
+}
+
+/// Compares tokens without returning early, so that how long it takes doesn't
+/// reveal how much of a token was right. Their digests are compared instead
+/// of the tokens themselves, so that it doesn't reveal their lengths either.
+fn tokens_match(valid: &str, token: &str) -> bool {
+  let valid = digest(&SHA256, valid.as_bytes());
+  let token = digest(&SHA256, token.as_bytes());
+  valid.as_ref().iter().zip(token.as_ref()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn matches_only_equal_tokens() {
+    assert!(tokens_match("secret", "secret"));
+    assert!(!tokens_match("secret", "secreT"));
+    assert!(!tokens_match("secret", "secret2"));
+    assert!(!tokens_match("secret", ""));
+  }
+
+  #[test]
+  fn gives_the_role_of_the_matching_token() {
+    let auth = AuthConfig {
+      auth_token: Some("admin".to_string()),
+      auth_tokens: vec![AuthToken { token: "viewer".to_string(), role: Role::ReadOnly }],
+    };
+    assert_eq!(auth.role_for_token("admin"), Some(Role::ReadWrite));
+    assert_eq!(auth.role_for_token("viewer"), Some(Role::ReadOnly));
+    assert_eq!(auth.role_for_token("view"), None);
+    assert_eq!(AuthConfig::default().role_for_token("anything"), Some(Role::ReadWrite));
+  }
+}
@@ -0,0 +1,388 @@
+//! The reloader's config options, and how they are layered and validated.
+//! Options are layered: defaults, then the config file, then the file's
+//! section for the detected game, then environment variables.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use ipnet::IpNet;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use tracing::level_filters::LevelFilter;
+
+mod auth;
+
+pub use auth::*;
+
+/// Environment variables named after options in upper case with this prefix,
+/// like `FXR_WS_PORT`, override the config file.
+const ENV_PREFIX: &str = "FXR_WS_";
+
+/// The option that holds the sections for specific games, keyed by
+/// `GameData.name`.
+const GAMES_OPTION: &str = "games";
+
+/// The names of every option, which are also the names of the fields of
+/// [`Config`] and [`AuthConfig`].
+const OPTIONS: &[&str] = &[
+  "port",
+  "console",
+  "port_attempts",
+  "bind",
+  "allowed_peers",
+  "max_message_size",
+  "ping_interval_secs",
+  "idle_timeout_secs",
+  "tls_cert",
+  "tls_key",
+  "json_lines_port",
+  "local_socket",
+  "auth_token",
+  "auth_tokens",
+  "log_level",
+  "log_max_files",
+];
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+  pub port: u16,
+  pub console: bool,
+  /// How many of the ports after `port` to try if it is taken.
+  pub port_attempts: u16,
+  /// The address the server listens on.
+  pub bind: IpAddr,
+  /// The IP addresses and ranges clients may connect from. Every address is
+  /// allowed if this is empty.
+  #[serde(deserialize_with = "deserialize_peers")]
+  pub allowed_peers: Vec<IpNet>,
+  /// The largest message clients may send, in bytes.
+  pub max_message_size: usize,
+  /// How often clients are pinged, in seconds. 0 disables pings.
+  pub ping_interval_secs: u64,
+  /// How long a client can go without sending anything, including pongs,
+  /// before it is disconnected, in seconds. 0 disables the timeout.
+  pub idle_timeout_secs: u64,
+  /// PEM certificate chain to serve `wss://` with. Relative to the DLL's
+  /// directory.
+  pub tls_cert: Option<PathBuf>,
+  /// PEM private key of the certificate. Relative to the DLL's directory.
+  pub tls_key: Option<PathBuf>,
+  /// Port of a TCP server that speaks newline-delimited JSON. It listens on
+  /// the same address as the WebSocket server. Disabled if not set.
+  pub json_lines_port: Option<u16>,
+  /// Named pipe (Windows) or Unix socket path of a local server that speaks
+  /// newline-delimited JSON. Disabled if not set.
+  pub local_socket: Option<String>,
+  #[serde(flatten)]
+  pub auth: AuthConfig,
+  /// The least severe level of records that are logged.
+  #[serde(deserialize_with = "deserialize_level")]
+  pub log_level: LevelFilter,
+  /// How many days of log files to keep.
+  pub log_max_files: usize,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      port: 24621,
+      console: false,
+      port_attempts: 10,
+      bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+      allowed_peers: Vec::new(),
+      max_message_size: 64 << 20,
+      ping_interval_secs: 30,
+      idle_timeout_secs: 90,
+      tls_cert: None,
+      tls_key: None,
+      json_lines_port: None,
+      local_socket: None,
+      auth: AuthConfig::default(),
+      log_level: LevelFilter::INFO,
+      log_max_files: 7,
+    }
+  }
+}
+
+impl Config {
+  pub fn allows_peer(&self, ip: IpAddr) -> bool {
+    // IPv4 clients of servers listening on IPv6 addresses have IPv4-mapped
+    // addresses
+    let ip = ip.to_canonical();
+    self.allowed_peers.is_empty() || self.allowed_peers.iter().any(|peers| peers.contains(&ip))
+  }
+
+  /// Resets options that were parsed but can't be used, and returns what was
+  /// wrong with them.
+  fn validate(&mut self) -> Vec<String> {
+    let mut errors = Vec::new();
+    let default = Config::default();
+    if self.port == 0 {
+      errors.push(format!("port can't be 0. Using {} instead.", default.port));
+      self.port = default.port;
+    }
+    if self.port_attempts == 0 {
+      errors.push(format!("port_attempts must be at least 1. Using {} instead.", default.port_attempts));
+      self.port_attempts = default.port_attempts;
+    }
+    if self.max_message_size == 0 {
+      errors.push(format!("max_message_size must be at least 1. Using {} instead.", default.max_message_size));
+      self.max_message_size = default.max_message_size;
+    }
+    if self.log_max_files == 0 {
+      errors.push(format!("log_max_files must be at least 1. Using {} instead.", default.log_max_files));
+      self.log_max_files = default.log_max_files;
+    }
+    if self.json_lines_port == Some(self.port) {
+      errors.push("json_lines_port can't be the same as port. The JSON lines server is disabled.".to_string());
+      self.json_lines_port = None;
+    }
+    if self.ping_interval_secs != 0 && self.idle_timeout_secs != 0 && self.idle_timeout_secs <= self.ping_interval_secs {
+      errors.push(format!(
+        "idle_timeout_secs must be longer than ping_interval_secs, or idle clients are disconnected before they are pinged. Using {} and {} instead.",
+        default.idle_timeout_secs, default.ping_interval_secs,
+      ));
+      self.idle_timeout_secs = default.idle_timeout_secs;
+      self.ping_interval_secs = default.ping_interval_secs;
+    }
+    errors
+  }
+
+  /// The names of the options that differ from another config and can only
+  /// be changed by restarting the game.
+  pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+    [
+      ("port", self.port != other.port),
+      ("console", self.console != other.console),
+      ("port_attempts", self.port_attempts != other.port_attempts),
+      ("bind", self.bind != other.bind),
+      ("tls_cert", self.tls_cert != other.tls_cert),
+      ("tls_key", self.tls_key != other.tls_key),
+      ("json_lines_port", self.json_lines_port != other.json_lines_port),
+      ("local_socket", self.local_socket != other.local_socket),
+      ("log_max_files", self.log_max_files != other.log_max_files),
+    ]
+      .into_iter()
+      .filter_map(|(name, changed)| changed.then_some(name))
+      .collect()
+  }
+}
+
+/// Parses IP addresses and CIDR ranges, like `192.168.1.20` and
+/// `192.168.1.0/24`.
+fn deserialize_peers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<IpNet>, D::Error> {
+  Vec::<String>::deserialize(deserializer)?.iter()
+    .map(|peer| {
+      peer.parse::<IpNet>()
+        .or_else(|_| peer.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| D::Error::custom(format!("invalid IP address or range: {}", peer)))
+    })
+    .collect()
+}
+
+/// Parses log levels, like `info` or `debug`.
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LevelFilter, D::Error> {
+  let level = String::deserialize(deserializer)?;
+  level.parse().map_err(|_| {
+    D::Error::custom(format!("invalid log level: {}. Expected off, error, warn, info, debug or trace", level))
+  })
+}
+
+/// Checks whether an option can be parsed on its own.
+fn check_option(name: &str, value: &Value) -> Result<(), serde_json::Error> {
+  let option = Map::from_iter([(name.to_string(), value.clone())]);
+  serde_json::from_value::<Config>(Value::Object(option)).map(|_| ())
+}
+
+/// Loads the config file and applies the section for the game and the
+/// environment variables `env` returns on top of it. Every option that is
+/// missing or invalid is left at its default, and what was wrong is returned
+/// along with the config.
+pub fn load(path: &Path, game: Option<&str>, env: impl Fn(&str) -> Option<String>) -> (Config, Vec<String>) {
+  let mut errors = Vec::new();
+  let mut options = match std::fs::read_to_string(path) {
+    Ok(text) => match serde_json::from_str(&text) {
+      Ok(Value::Object(options)) => options,
+      Ok(_) => {
+        errors.push(format!("{} must contain a JSON object. Using the default config.", path.display()));
+        Map::new()
+      }
+      Err(e) => {
+        errors.push(format!("Failed to parse {}: {}. Using the default config.", path.display(), e));
+        Map::new()
+      }
+    },
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Map::new(),
+    Err(e) => {
+      errors.push(format!("Failed to read {}: {}. Using the default config.", path.display(), e));
+      Map::new()
+    }
+  };
+
+  match options.remove(GAMES_OPTION) {
+    Some(Value::Object(mut games)) => match game.and_then(|game| games.remove(game)) {
+      Some(Value::Object(section)) => options.extend(section),
+      Some(_) => errors.push(format!("The {} section for {} must be an object.", GAMES_OPTION, game.unwrap_or_default())),
+      None => {}
+    },
+    Some(_) => errors.push(format!("{} must be an object with a section for each game.", GAMES_OPTION)),
+    None => {}
+  }
+
+  for name in OPTIONS {
+    let Some(text) = env(&format!("{}{}", ENV_PREFIX, name.to_uppercase())) else {
+      continue;
+    };
+    // Values that aren't valid JSON for the option are taken as strings, so
+    // that addresses and tokens don't have to be quoted
+    let value = serde_json::from_str(&text).ok()
+      .filter(|value| check_option(name, value).is_ok())
+      .unwrap_or(Value::String(text));
+    options.insert(name.to_string(), value);
+  }
+
+  options.retain(|name, value| {
+    if !OPTIONS.contains(&name.as_str()) {
+      errors.push(format!("Unknown option {}.", name));
+      return false;
+    }
+    match check_option(name, value) {
+      Ok(()) => true,
+      Err(e) => {
+        errors.push(format!("Invalid {}: {}. Using the default.", name, e));
+        false
+      }
+    }
+  });
+  let mut config: Config = serde_json::from_value(Value::Object(options)).unwrap_or_else(|e| {
+    errors.push(format!("Invalid config: {}. Using the default config.", e));
+    Config::default()
+  });
+  errors.extend(config.validate());
+  (config, errors)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Writes a config file to a temporary directory and loads it without any
+  /// environment variables.
+  fn load_text(name: &str, text: &str, game: Option<&str>) -> (Config, Vec<String>) {
+    load_text_with_env(name, text, game, &[])
+  }
+
+  fn load_text_with_env(name: &str, text: &str, game: Option<&str>, env: &[(&str, &str)]) -> (Config, Vec<String>) {
+    let path = std::env::temp_dir().join(format!("fxr_ws_config_test_{}_{}.json", std::process::id(), name));
+    std::fs::write(&path, text).unwrap();
+    let loaded = load(&path, game, |var| {
+      env.iter().find(|(name, _)| *name == var).map(|(_, value)| value.to_string())
+    });
+    std::fs::remove_file(&path).unwrap();
+    loaded
+  }
+
+  #[test]
+  fn uses_defaults_without_a_file() {
+    let (config, errors) = load(Path::new("does_not_exist.json"), None, |_| None);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(config.port, Config::default().port);
+  }
+
+  #[test]
+  fn keeps_defaults_for_missing_options() {
+    let (config, errors) = load_text("missing", r#"{ "port": 12345 }"#, None);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(config.port, 12345);
+    assert_eq!(config.ping_interval_secs, Config::default().ping_interval_secs);
+  }
+
+  #[test]
+  fn applies_the_section_for_the_game() {
+    let text = r#"{ "port": 1000, "console": true, "games": { "ER": { "port": 2000 }, "DS3": { "port": 3000 } } }"#;
+    let (config, errors) = load_text("games", text, Some("ER"));
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(config.port, 2000);
+    assert!(config.console);
+
+    let (config, _) = load_text("other_game", text, Some("Sekiro"));
+    assert_eq!(config.port, 1000);
+  }
+
+  #[test]
+  fn environment_variables_override_the_file() {
+    let text = r#"{ "log_max_files": 3, "games": { "ER": { "log_max_files": 4 } } }"#;
+    let (config, errors) = load_text_with_env("env", text, Some("ER"), &[("FXR_WS_LOG_MAX_FILES", "5")]);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(config.log_max_files, 5);
+  }
+
+  #[test]
+  fn environment_variables_can_be_unquoted_strings() {
+    let env = [("FXR_WS_BIND", "0.0.0.0"), ("FXR_WS_AUTH_TOKEN", "123"), ("FXR_WS_LOCAL_SOCKET", "fxr")];
+    let (config, errors) = load_text_with_env("env_strings", "{}", None, &env);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(config.bind, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    assert_eq!(config.auth.auth_token.as_deref(), Some("123"));
+    assert_eq!(config.local_socket.as_deref(), Some("fxr"));
+  }
+
+  #[test]
+  fn rejects_invalid_environment_variables() {
+    let (config, errors) = load_text_with_env("env_invalid", r#"{ "port": 12345 }"#, None, &[("FXR_WS_PORT", "abc")]);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].contains("port"));
+    assert_eq!(config.port, Config::default().port);
+  }
+
+  #[test]
+  fn rejects_invalid_peers() {
+    let (config, errors) = load_text("peers", r#"{ "allowed_peers": ["192.168.1.0/33"], "port": 12345 }"#, None);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].contains("allowed_peers"));
+    assert!(config.allowed_peers.is_empty());
+    assert_eq!(config.port, 12345);
+  }
+
+  #[test]
+  fn rejects_port_zero() {
+    let (config, errors) = load_text("port", r#"{ "port": 0 }"#, None);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!(config.port, Config::default().port);
+  }
+
+  #[test]
+  fn rejects_invalid_ping_intervals() {
+    let (config, errors) = load_text("ping", r#"{ "ping_interval_secs": -1 }"#, None);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!(config.ping_interval_secs, Config::default().ping_interval_secs);
+  }
+
+  #[test]
+  fn resets_idle_timeouts_shorter_than_the_ping_interval() {
+    let (config, errors) = load_text("ping_idle", r#"{ "ping_interval_secs": 60, "idle_timeout_secs": 60 }"#, None);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!(config.ping_interval_secs, Config::default().ping_interval_secs);
+    assert_eq!(config.idle_timeout_secs, Config::default().idle_timeout_secs);
+  }
+
+  #[test]
+  fn zero_ping_interval_disables_pings() {
+    let (config, errors) = load_text("no_ping", r#"{ "ping_interval_secs": 0 }"#, None);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(config.ping_interval_secs, 0);
+  }
+
+  #[test]
+  fn reports_unknown_options() {
+    let (_, errors) = load_text("unknown", r#"{ "prot": 12345 }"#, None);
+    assert_eq!(errors, ["Unknown option prot."]);
+  }
+
+  #[test]
+  fn lists_options_that_require_a_restart() {
+    let config = Config::default();
+    let changed = Config { port: 1, max_message_size: 1, log_max_files: 1, ..Config::default() };
+    assert_eq!(changed.restart_required(&config), ["port", "log_max_files"]);
+  }
+}
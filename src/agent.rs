@@ -1,10 +1,26 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use futures_util::{SinkExt, StreamExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::future::{self, BoxFuture};
+use futures_util::{FutureExt, SinkExt, StreamExt};
 use once_cell::sync::OnceCell;
-use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::accept_hdr_async_with_config;
+use tokio_tungstenite::tungstenite::{
+  handshake::server::{ErrorResponse, Request as HandshakeRequest, Response as HandshakeResponse},
+  http::{HeaderValue, StatusCode},
+  protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig},
+  Error as WsError,
+  Message,
+};
 use windows::Win32::Foundation::BOOL;
 use windows::core::PCWSTR;
 use windows::Win32::System::LibraryLoader::{GetModuleFileNameW, GetModuleHandleW};
@@ -12,66 +28,154 @@ use base64::{engine::general_purpose, Engine as _};
 use eldenring::fd4::FD4ParamRepository;
 use eldenring_util::singleton;
 use patcher::game::game_data::GameData;
+use protocol::{
+  compress_frame,
+  decode_frame,
+  decompress_frame,
+  encode_frame,
+  feature,
+  request_types,
+  Capabilities,
+  ErrorCode,
+  EventTopic,
+  FrameError,
+  FxrData,
+  LogLevel,
+  Request,
+  RequestPayload,
+  Response,
+  ResponseData,
+  ResponseError,
+  Role,
+  RpcError,
+  RpcNotification,
+  RpcRequest,
+  RpcResponse,
+  ServerEvent,
+  ServerInfo,
+  JSONRPC_PATH,
+  PROTOCOL_VERSION,
+  REQUEST_TYPE_NAMES,
+  RPC_INVALID_REQUEST,
+  RPC_PARSE_ERROR,
+  ZSTD_PROTOCOL,
+};
+use tracing::{debug, error, info, warn, Instrument};
 
-static RUNTIME: OnceCell<tokio::runtime::Runtime> = OnceCell::new();
-static PARAM_REQ_CHANNEL: OnceCell<(mpsc::Sender<ParamsRequestType>, mpsc::Receiver<Response>)> = OnceCell::new();
+use config::Config;
+
+mod auth;
+mod config;
+mod discovery;
+mod events;
+mod rest;
+mod lines;
+mod logging;
+mod metrics;
+mod shutdown;
+mod tls;
+
+static PARAM_REQ_CHANNEL: OnceCell<(mpsc::Sender<ParamsJob>, mpsc::Receiver<Response>)> = OnceCell::new();
 static GAME_DATA: OnceCell<GameData> = OnceCell::new();
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How many FXRs `get_fxrs` extracts at a time between checking whether the
+/// request has been cancelled.
+const EXTRACT_CHUNK_SIZE: usize = 64;
+
+/// How long clients have to finish the TLS handshake after connecting.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
-#[derive(Deserialize, Debug)]
-struct Config {
-  port: u16,
-  console: bool,
-}
-
-#[derive(Deserialize, Debug)]
-enum RequestType {
-  #[serde(rename = "reload_fxrs")]
-  ReloadFXRs,
-  #[serde(rename = "set_resident_sfx")]
-  SetResidentSFX,
-  #[serde(rename = "set_sp_effect_sfx")]
-  SetSpEffectSFX,
-  #[serde(rename = "get_fxr")]
-  GetFXR,
-  #[serde(rename = "get_fxrs")]
-  GetFXRs,
-  #[serde(rename = "list_fxrs")]
-  ListFXRs,
-  #[serde(other)]
-  Unknown,
-}
-
-const REQUEST_TYPE_NAMES: &[&str] = &[
-  "reload_fxrs",
-  "set_resident_sfx",
-  "set_sp_effect_sfx",
-  "get_fxr",
-  "get_fxrs",
-  "list_fxrs",
-];
-
-impl Default for RequestType {
-  fn default() -> Self {
-    RequestType::Unknown
-  }
-}
-
-#[derive(Deserialize, Debug)]
-struct Request {
-  request_id: String,
-  #[serde(rename = "type", default)]
-  request_type: RequestType,
-  #[serde(flatten, default)]
-  params: serde_json::Value,
-}
-
-#[derive(Serialize)]
-struct Response {
-  request_id: String,
-  success: bool,
-  message: String,
-  #[serde(skip_serializing_if = "Option::is_none")]
-  data: Option<serde_json::Value>,
+/// FXRs received with a request, and FXRs to send back with its response.
+/// Requests received in binary frames are responded to with binary frames,
+/// where FXRs are sent as attachments instead of base64 strings.
+#[derive(Default)]
+struct Attachments {
+  binary: bool,
+  received: Vec<Vec<u8>>,
+  to_send: Vec<Vec<u8>>,
+}
+
+/// The error for an FXR in `fxrs` that `Attachments::resolve` failed on.
+fn attachment_error(fxr: &FxrData) -> ResponseError {
+  let attachment = match fxr {
+    FxrData::Attachment { attachment } => Some(*attachment),
+    FxrData::Base64(_) => None,
+  };
+  ResponseError {
+    param: Some("fxrs".to_string()),
+    attachment,
+    ..ResponseError::new(ErrorCode::InvalidParam)
+  }
+}
+
+impl Attachments {
+  fn binary(received: Vec<Vec<u8>>) -> Self {
+    Self { binary: true, received, to_send: Vec::new() }
+  }
+
+  fn resolve(&self, fxr: &FxrData) -> Result<Vec<u8>, String> {
+    match fxr {
+      FxrData::Base64(base64_str) => general_purpose::STANDARD.decode(base64_str)
+        .map_err(|e| format!("Failed to decode base64 FXR: {}", e)),
+      FxrData::Attachment { attachment } => self.received.get(*attachment as usize)
+        .cloned()
+        .ok_or_else(|| format!("Missing FXR attachment {}", attachment)),
+    }
+  }
+
+  fn add(&mut self, fxr: Vec<u8>) -> FxrData {
+    if self.binary {
+      self.to_send.push(fxr);
+      FxrData::Attachment { attachment: (self.to_send.len() - 1) as u32 }
+    } else {
+      FxrData::Base64(general_purpose::STANDARD.encode(&fxr))
+    }
+  }
+}
+
+/// How messages are encoded on a connection, negotiated during the handshake.
+#[derive(Clone, Copy, Default)]
+struct Encoding {
+  zstd: bool,
+  jsonrpc: bool,
+}
+
+impl Encoding {
+  fn response(&self, response: Response, attachments: &Attachments) -> Message {
+    let text = if self.jsonrpc {
+      serde_json::to_string(&RpcResponse::from(response)).unwrap()
+    } else {
+      serde_json::to_string(&response).unwrap()
+    };
+    self.message(text, attachments)
+  }
+
+  /// Events are sent as JSON-RPC notifications where the method is the
+  /// event's type.
+  fn event(&self, event: &ServerEvent) -> Message {
+    let text = if self.jsonrpc {
+      let mut params = serde_json::to_value(event).unwrap();
+      let method = params.as_object_mut()
+        .and_then(|params| params.remove("type"))
+        .and_then(|method| method.as_str().map(str::to_string))
+        .unwrap_or_default();
+      serde_json::to_string(&RpcNotification::new(method, params)).unwrap()
+    } else {
+      serde_json::to_string(event).unwrap()
+    };
+    Message::Text(text)
+  }
+
+  /// Sends the text in a binary frame if the request was received in one.
+  fn message(&self, text: String, attachments: &Attachments) -> Message {
+    if attachments.binary {
+      let frame = encode_frame(&text, &attachments.to_send);
+      Message::Binary(if self.zstd { compress_frame(&frame) } else { frame })
+    } else {
+      Message::Text(text)
+    }
+  }
 }
 
 #[derive(Clone)]
@@ -80,6 +184,118 @@ enum ParamsRequestType {
   SetSpEffectSFX { sp_effect_id: u32, sfx_id: i32, dmy_id: i16, target_vfx_id: Option<i32> },
 }
 
+/// A params request and a sender that is notified once it has been applied.
+/// Requests are skipped if the receiver is dropped before they are applied,
+/// which happens when they are cancelled or time out.
+type ParamsJob = (ParamsRequestType, oneshot::Sender<()>);
+
+/// State shared by the requests of a connection.
+struct Session {
+  client_id: u64,
+  game_data: GameData,
+  params_sender: mpsc::Sender<ParamsJob>,
+  subscriptions: events::Subscriptions,
+  role: Mutex<Role>,
+  in_flight: Mutex<InFlight>,
+  /// Whether the transport can carry binary frames.
+  binary_frames: bool,
+  /// Whether binary frames are compressed with zstd on this connection.
+  zstd: bool,
+}
+
+impl Session {
+  fn new(client_id: u64, game_data: GameData, role: Role, binary_frames: bool, zstd: bool) -> Self {
+    Self {
+      client_id,
+      game_data,
+      params_sender: PARAM_REQ_CHANNEL.get().unwrap().0.clone(),
+      subscriptions: events::Subscriptions::default(),
+      role: Mutex::new(role),
+      in_flight: Mutex::default(),
+      binary_frames,
+      zstd,
+    }
+  }
+}
+
+/// Requests that are waiting or being handled, so that they can be cancelled.
+/// Jobs are keyed by a counter rather than by request ID, since request IDs
+/// are chosen by the client.
+#[derive(Default)]
+struct InFlight {
+  next_job: u64,
+  /// Senders that cancel jobs, by job ID.
+  jobs: HashMap<u64, oneshot::Sender<()>>,
+  /// The job handling each request, by request ID. JSON-RPC notifications
+  /// have no ID to cancel them by, so they are left out.
+  request_ids: HashMap<String, u64>,
+}
+
+impl InFlight {
+  /// Registers a job. Returns `None` if another request with the same ID is
+  /// still in flight.
+  fn start(&mut self, request_id: Option<&str>) -> Option<(u64, oneshot::Receiver<()>)> {
+    if request_id.is_some_and(|request_id| self.request_ids.contains_key(request_id)) {
+      return None;
+    }
+    let job_id = self.next_job;
+    self.next_job += 1;
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    self.jobs.insert(job_id, cancel_tx);
+    if let Some(request_id) = request_id {
+      self.request_ids.insert(request_id.to_string(), job_id);
+    }
+    Some((job_id, cancel_rx))
+  }
+
+  /// Removes a job once it has finished.
+  fn finish(&mut self, job_id: u64, request_id: &str) {
+    self.jobs.remove(&job_id);
+    if self.request_ids.get(request_id) == Some(&job_id) {
+      self.request_ids.remove(request_id);
+    }
+  }
+
+  /// Cancels the request with the given ID. Returns false if it isn't in
+  /// flight.
+  fn cancel(&mut self, request_id: &str) -> bool {
+    let Some(job_id) = self.request_ids.remove(request_id) else {
+      return false;
+    };
+    match self.jobs.remove(&job_id) {
+      Some(cancel) => cancel.send(()).is_ok(),
+      None => false,
+    }
+  }
+}
+
+/// Where the response to a request goes.
+enum ReplyTo {
+  /// Sent to the client by itself.
+  Client,
+  /// Collected into the response to the JSON-RPC batch the request was in.
+  Batch(oneshot::Sender<Response>),
+  /// Dropped, for JSON-RPC notifications.
+  Nobody,
+}
+
+impl ReplyTo {
+  fn responds(&self) -> bool {
+    !matches!(self, ReplyTo::Nobody)
+  }
+}
+
+/// A request waiting to be handled.
+struct Job {
+  request: Request,
+  attachments: Attachments,
+  reply_to: ReplyTo,
+  deadline: Option<Instant>,
+  /// The job's ID in `Session::in_flight` and the receiver that cancels it.
+  /// `None` for cancel requests, which can't be cancelled themselves.
+  cancelled: Option<(u64, oneshot::Receiver<()>)>,
+}
+
 fn get_dll_dir_path() -> Option<PathBuf> {
   let dll_name = "fxr_ws_reloader.dll\0";
   let wide_dll_name: Vec<u16> = dll_name.encode_utf16().collect();
@@ -105,538 +321,1456 @@ pub extern "system" fn DllMain(
     std::thread::spawn(|| {
       let runtime = tokio::runtime::Runtime::new().unwrap();
 
-      let game_data = match patcher::game::detection::detect_running_game() {
+      // The game is detected before anything is logged so that the config
+      // can be loaded with the game's section, which decides how to log
+      let detected = patcher::game::detection::detect_running_game();
+      let game_name = detected.as_ref().ok().map(|game_data| game_data.name);
+      let config_path = config::path();
+      let (config, errors) = config::load(&config_path, game_name);
+
+      if config.console {
+        unsafe {
+          windows::Win32::System::Console::AllocConsole();
+        }
+      }
+      logging::init(&config);
+      for error in &errors {
+        warn!("Config error: {}", error);
+      }
+
+      let game_data = match detected {
         Ok(data) => data,
         Err(e) => {
-          eprintln!("Failed to detect a supported game: {}", e);
+          error!("Failed to detect a supported game: {}", e);
           return;
         }
       };
       if GAME_DATA.set(game_data).is_err() {
-        eprintln!("Failed to set GAME_DATA");
+        error!("Failed to set GAME_DATA");
         return;
       }
+      let config = config::set(config);
 
-      let config: Config = {
-        let config_path = get_dll_dir_path()
-          .map(|p| p.join("fxr_ws_reloader_config.json"))
-          .unwrap_or_else(|| PathBuf::from("fxr_ws_reloader_config.json"));
-
-        let config_str = std::fs::read_to_string(config_path)
-          .unwrap_or_else(|_| String::from(r#"{"port": 24621}"#));
-        serde_json::from_str(&config_str).unwrap_or(Config { port: 24621, console: false })
-      };
-
-      if config.console {
-        unsafe {
-          windows::Win32::System::Console::AllocConsole();
-        }
-      }
-
-      let (tx, rx) = mpsc::channel::<ParamsRequestType>(32);
+      let (tx, rx) = mpsc::channel::<ParamsJob>(32);
       let (response_tx, response_rx) = mpsc::channel::<Response>(32);
       PARAM_REQ_CHANNEL.set((tx.clone(), response_rx)).unwrap();
 
-      runtime.spawn(game_param_handler(rx, response_tx));
+      let (stop_params_tx, stop_params_rx) = oneshot::channel();
+      let param_handle = runtime.spawn(game_param_handler(rx, response_tx, stop_params_rx));
 
-      runtime.spawn(async move {
-        start_websocket_server(config.port).await;
+      let server_handle = runtime.spawn(async move {
+        start_websocket_server(config).await;
       });
+      runtime.spawn(config::watch(config_path, game_name));
 
-      RUNTIME.set(runtime).unwrap();
+      // This thread keeps the runtime until the server is shut down so that it
+      // can be stopped in order
+      runtime.block_on(async {
+        shutdown::started().await;
+        let _ = server_handle.await;
+        if tokio::time::timeout(shutdown::DRAIN_TIMEOUT, shutdown::drained()).await.is_err() {
+          warn!("Some connections did not finish their requests in time");
+        }
+        // Nothing can queue params requests anymore, so the handler only has
+        // to finish the ones that are left
+        let _ = stop_params_tx.send(());
+        let _ = param_handle.await;
+      });
+      runtime.shutdown_timeout(shutdown::DRAIN_TIMEOUT);
+      discovery::remove();
+      info!("The reloader has been shut down");
     });
   } else if reason == 0 { // DLL_PROCESS_DETACH
-    if let Some(_) = RUNTIME.get() {
-      // Runtime will be dropped automatically when DLL is unloaded
-    }
+    // Nothing is done here, since this runs while holding the loader lock,
+    // and the runtime's threads need it to exit. Clients that unload the DLL
+    // should send a shutdown request and wait for the connection to close
+    // first, which also removes the discovery file.
   }
   BOOL(1)
 }
 
-async fn start_websocket_server(port: u16) {
-  let addr = format!("127.0.0.1:{}", port);
-  let listener = TcpListener::bind(&addr).await.unwrap();
+async fn start_websocket_server(config: Arc<Config>) {
+  // Falling back to an unencrypted server could expose what TLS was meant
+  // to protect
+  let tls = match config::tls_acceptor(&config) {
+    Ok(tls) => tls,
+    Err(e) => {
+      error!("{}. The server was not started.", e);
+      return;
+    }
+  };
+  metrics::start();
+  let Some((listener, addr)) = bind_listener(&config).await else {
+    return;
+  };
   let game_info = GAME_DATA.get()
     .map(|g| format!(" | Game: {}", g.name))
     .unwrap_or_else(|| " | Game: ERROR: Unsupported or undetected game".to_string());
-  println!(
-    "WebSocket server listening on: {} | Version: {}{}",
+  info!(
+    "WebSocket server listening on: {}://{} | Version: {}{}",
+    if tls.is_some() { "wss" } else { "ws" },
     addr,
     env!("CARGO_PKG_VERSION"),
     game_info
   );
 
-  while let Ok((stream, _)) = listener.accept().await {
-    tokio::spawn(handle_connection(stream));
+  discovery::write(addr, tls.is_some(), GAME_DATA.get().map(|g| g.name));
+
+  if let Some(game_data) = GAME_DATA.get().filter(|g| g.features.extract) {
+    tokio::spawn(events::watch_fxrs(*game_data));
+  }
+
+  if !config.bind.is_loopback() && config.allowed_peers.is_empty() && !config.auth.required() {
+    warn!("Any computer that can reach {} can control the game. Set allowed_peers or auth_token to restrict this.", addr);
+  }
+
+  if let Some(port) = config.json_lines_port {
+    tokio::spawn(lines::serve_tcp(config.clone(), port, tls.clone()));
+  }
+  if let Some(name) = config.local_socket.clone() {
+    tokio::spawn(lines::serve_local(name));
+  }
+
+  loop {
+    let (stream, peer) = tokio::select! {
+      accepted = listener.accept() => match accepted {
+        Ok(accepted) => accepted,
+        Err(_) => break,
+      },
+      _ = shutdown::started() => break,
+    };
+    // Connections use the config as it was when they were accepted, so that
+    // changes to the file apply to new connections
+    let config = config::current();
+    if !config.allows_peer(peer.ip()) {
+      warn!("Rejected connection from {}, which is not in allowed_peers", peer);
+      continue;
+    }
+    match &tls {
+      Some(tls) => {
+        let tls = tls.clone();
+        tokio::spawn(async move {
+          if let Some(stream) = accept_tls(&tls, stream, peer).await {
+            rest::handle_stream(stream, peer, config).await;
+          }
+        });
+      }
+      None => {
+        tokio::spawn(rest::handle_stream(stream, peer, config));
+      }
+    }
   }
+  info!("WebSocket server stopped accepting connections");
 }
 
-async fn handle_connection(stream: TcpStream) {
-  if let Ok(addr) = stream.peer_addr() {
-    println!("New WebSocket connection from {}", addr);
+/// Binds the WebSocket server to the configured port, or to one of the ports
+/// after it if it is taken, for example by the reloader of another instance
+/// of the game.
+async fn bind_listener(config: &Config) -> Option<(TcpListener, SocketAddr)> {
+  let last_port = config.port.saturating_add(config.port_attempts.saturating_sub(1));
+  for port in config.port..=last_port {
+    let addr = SocketAddr::new(config.bind, port);
+    match TcpListener::bind(&addr).await {
+      Ok(listener) => return Some((listener, addr)),
+      Err(e) => warn!("Failed to listen on {}: {}", addr, e),
+    }
+  }
+  error!("No port from {} to {} was available. The server was not started.", config.port, last_port);
+  None
+}
+
+/// Finishes the TLS handshake with a client that just connected.
+async fn accept_tls(tls: &TlsAcceptor, stream: TcpStream, peer: SocketAddr) -> Option<tokio_rustls::server::TlsStream<TcpStream>> {
+  match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+    Ok(Ok(stream)) => Some(stream),
+    Ok(Err(e)) => {
+      warn!("TLS handshake with {} failed: {}", peer, e);
+      None
+    }
+    Err(_) => {
+      warn!("TLS handshake with {} timed out", peer);
+      None
+    }
+  }
+}
+
+/// A binary frame that couldn't be read, with the ID of the request in it if
+/// it could be found in what there is of the header.
+struct InvalidFrame {
+  error: FrameError,
+  request_id: Option<String>,
+}
+
+/// Decodes a binary frame into its header and attachments, decompressing it
+/// first if zstd was negotiated for the connection. Decompressed frames can't
+/// be larger than `max_size`.
+fn read_binary_frame(bytes: &[u8], zstd: bool, max_size: usize) -> Result<(String, Vec<Vec<u8>>), InvalidFrame> {
+  let decompressed;
+  let bytes = if zstd {
+    decompressed = decompress_frame(bytes, max_size).map_err(|error| InvalidFrame { error, request_id: None })?;
+    &decompressed
   } else {
-    println!("New WebSocket connection from unknown address");
+    bytes
+  };
+  match decode_frame(bytes) {
+    Ok(frame) => Ok((frame.header.to_string(), frame.attachments.iter().map(|a| a.to_vec()).collect())),
+    // The header starts after its length
+    Err(error) => Err(InvalidFrame {
+      error,
+      request_id: find_request_id(&String::from_utf8_lossy(bytes.get(4..).unwrap_or_default())),
+    }),
   }
-  let ws_stream = accept_async(stream).await.unwrap();
-  let (mut write, mut read) = ws_stream.split();
+}
 
-  let game_data = match GAME_DATA.get() {
-    Some(data) => data.clone(),
-    None => {
-      let response = serde_json::json!({
-        "type": "server_info",
-        "version": env!("CARGO_PKG_VERSION"),
-        "error": "Failed to detect a supported game."
-      });
-      if let Err(e) = write.send(Message::Text(response.to_string())).await {
-        eprintln!("Failed to send error message: {}", e);
-        return;
-      }
+async fn handle_connection<S>(stream: S, peer: SocketAddr, config: Arc<Config>)
+where
+  S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+  let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+  info!("New WebSocket connection from {} (client {})", peer, client_id);
+  let mut encoding = Encoding::default();
+  let mut role = config.auth.initial_role();
+  let ws_config = WebSocketConfig {
+    max_message_size: Some(config.max_message_size),
+    max_frame_size: Some(config.max_message_size),
+    ..Default::default()
+  };
+  // tungstenite's callback can only reject the handshake with a full HTTP
+  // response
+  #[allow(clippy::result_large_err)]
+  let callback = |request: &HandshakeRequest, mut response: HandshakeResponse| {
+    encoding.jsonrpc = request.uri().path() == JSONRPC_PATH;
+    if let Some(token) = auth::handshake_token(request) {
+      let Some(token_role) = config.auth.role_for_token(&token) else {
+        let mut response = ErrorResponse::new(Some("Invalid auth token".to_string()));
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return Err(response);
+      };
+      role = token_role;
+    }
+    let offered = request.headers().get_all("Sec-WebSocket-Protocol").iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .any(|protocol| protocol.trim() == ZSTD_PROTOCOL);
+    if offered {
+      response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(ZSTD_PROTOCOL));
+      encoding.zstd = true;
+    }
+    Ok(response)
+  };
+  let handshake = accept_hdr_async_with_config(stream, callback, Some(ws_config)).await;
+  let ws_stream = match handshake {
+    Ok(ws_stream) => ws_stream,
+    Err(e) => {
+      warn!("WebSocket handshake with client {} failed: {}", client_id, e);
       return;
     }
   };
+  let (mut write, mut read) = ws_stream.split();
 
-  let server_info = serde_json::json!({
-    "type": "server_info",
-    "version": env!("CARGO_PKG_VERSION"),
-    "game": game_data.name,
-    "features": game_data.features
-  });
-  if let Err(e) = write.send(Message::Text(server_info.to_string())).await {
-    eprintln!("Failed to send server info: {}", e);
+  let server_info = encoding.event(&ServerEvent::ServerInfo(server_info(client_id, role)));
+  if let Err(e) = write.send(server_info).await {
+    warn!("Failed to send server info: {}", e);
     return;
   }
+  let Some(game_data) = GAME_DATA.get().copied() else {
+    return;
+  };
+  info!("Client {} connected with the {:?} role", client_id, role);
+  let _connected = metrics::connected();
 
-  let params_sender = PARAM_REQ_CHANNEL.get().unwrap().0.clone();
-  let (response_tx, mut response_rx) = mpsc::channel::<(String, Response)>(32);
+  let (response_tx, mut response_rx) = mpsc::channel::<(String, Message)>(32);
   let write_handle = tokio::spawn(async move {
-    while let Some((id, response)) = response_rx.recv().await {
-      let response_text = serde_json::to_string(&response).unwrap();
-      if let Err(e) = write.send(Message::Text(response_text)).await {
-        eprintln!("Error sending response for request {}: {}", id, e);
+    while let Some((id, message)) = response_rx.recv().await {
+      metrics::sent(message.len());
+      if let Err(e) = write.send(message).await {
+        warn!("Error sending response for request {}: {}", id, e);
         break;
       }
     }
   });
 
-  while let Some(msg) = read.next().await {
-    if let Ok(msg) = msg {
-      if let Message::Text(text) = msg {
-        let request = match serde_json::from_str::<Request>(&text) {
-          Ok(request) => request,
-          Err(e) => {
-            let response = Response {
-              request_id: ":ERROR:".into(),
-              success: false,
-              message: format!("Invalid request format: {}", e),
-              data: None,
-            };
-            if let Err(e) = response_tx.send(("error".to_string(), response)).await {
-              eprintln!("Error sending error response: {}", e);
-              break;
-            }
-            continue;
+  let session = Session::new(client_id, game_data, role, true, encoding.zstd);
+  let connection = Connection::start(session, encoding, config.clone(), response_tx.clone());
+
+  let ping_interval = Duration::from_secs(config.ping_interval_secs);
+  let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+  let mut pings = tokio::time::interval_at(Instant::now() + ping_interval, ping_interval.max(Duration::from_secs(1)));
+  let mut last_received = Instant::now();
+  let mut shutting_down = false;
+  loop {
+    let idle_deadline = (!idle_timeout.is_zero()).then(|| last_received + idle_timeout);
+    let msg = tokio::select! {
+      msg = read.next() => msg,
+      _ = pings.tick(), if !ping_interval.is_zero() => {
+        if response_tx.send(("ping".to_string(), Message::Ping(Vec::new()))).await.is_err() {
+          break;
+        }
+        continue;
+      }
+      _ = sleep_until_deadline(idle_deadline) => {
+        info!("Disconnecting client {} after {} seconds without messages", client_id, idle_timeout.as_secs());
+        close(&response_tx, CloseCode::Away, "Idle timeout").await;
+        break;
+      }
+      _ = shutdown::started() => {
+        shutting_down = true;
+        break;
+      }
+    };
+    last_received = Instant::now();
+    if let Some(Ok(message)) = &msg {
+      metrics::received(message.len());
+    }
+
+    let (text, attachments) = match msg {
+      None | Some(Ok(Message::Close(_))) => break,
+      Some(Ok(Message::Text(text))) => (text, Attachments::default()),
+      Some(Ok(Message::Binary(bytes))) => match read_binary_frame(&bytes, encoding.zstd, config.max_message_size) {
+        Ok((text, received)) => (text, Attachments::binary(received)),
+        Err(InvalidFrame { error, request_id }) => {
+          metrics::invalid_request(ErrorCode::InvalidFrame);
+          let response = Response {
+            request_id: request_id.unwrap_or_else(|| ":ERROR:".into()),
+            success: false,
+            message: format!("Invalid binary frame: {}", error),
+            data: None,
+            error: Some(ResponseError::new(ErrorCode::InvalidFrame)),
+          };
+          if !connection.send_error(response).await {
+            break;
           }
-        };
+          continue;
+        }
+      },
+      // Pings are answered automatically, and pongs only need to reset the
+      // idle timeout
+      Some(Ok(_)) => continue,
+      Some(Err(WsError::Capacity(e))) => {
+        warn!("Client {} sent a message that is too large: {}", client_id, e);
+        close(&response_tx, CloseCode::Size, "Message too large").await;
+        break;
+      }
+      Some(Err(e)) => {
+        warn!("Error reading from client {}: {}", client_id, e);
+        break;
+      }
+    };
 
-        let request_id = request.request_id.clone();
-        let response_tx = response_tx.clone();
-        let params_sender = params_sender.clone();
-        let game_data = game_data.clone();
+    if !connection.handle_message(text, attachments).await {
+      break;
+    }
+  }
 
-        match request.request_type {
-          RequestType::SetResidentSFX | RequestType::SetSpEffectSFX | RequestType::ReloadFXRs => {
-            let response = handle_request(request, params_sender, game_data).await;
-            if let Err(e) = response_tx.send((request_id, response)).await {
-              eprintln!("Error sending response: {}", e);
+  connection.finish().await;
+  if shutting_down {
+    close(&response_tx, CloseCode::Away, "Server is shutting down").await;
+  }
+  drop(response_tx);
+  let _ = write_handle.await;
+}
+
+/// Builds the `server_info` event clients are sent when they connect.
+fn server_info(client_id: u64, role: Role) -> ServerInfo {
+  let game_data = GAME_DATA.get();
+  ServerInfo {
+    version: env!("CARGO_PKG_VERSION").to_string(),
+    protocol_version: Some(PROTOCOL_VERSION),
+    game: game_data.map(|g| g.name.to_string()),
+    features: game_data.map(|g| g.features),
+    client_id: Some(client_id),
+    role: Some(role),
+    error: game_data.is_none().then(|| "Failed to detect a supported game.".to_string()),
+  }
+}
+
+/// The part of a connection that doesn't depend on the transport. Transports
+/// pass the messages they read to `handle_message` and write whatever is sent
+/// to the response channel.
+struct Connection {
+  session: Arc<Session>,
+  config: Arc<Config>,
+  encoding: Encoding,
+  response_tx: mpsc::Sender<(String, Message)>,
+  job_tx: mpsc::Sender<Job>,
+  event_handle: JoinHandle<()>,
+  dispatch_handle: JoinHandle<()>,
+  /// Tasks that send the responses to JSON-RPC batches once all of their
+  /// requests have finished.
+  rpc_batches: Mutex<JoinSet<()>>,
+}
+
+impl Connection {
+  /// Starts forwarding events to the client and handling its requests.
+  fn start(session: Session, encoding: Encoding, config: Arc<Config>, response_tx: mpsc::Sender<(String, Message)>) -> Self {
+    let session = Arc::new(session);
+    let client_id = session.client_id;
+
+    let mut event_rx = events::receiver();
+    let event_session = session.clone();
+    let event_tx = response_tx.clone();
+    let event_handle = tokio::spawn(async move {
+      loop {
+        match event_rx.recv().await {
+          Ok(event) => {
+            if event.origin() == Some(client_id) || *event_session.role.lock().unwrap() == Role::Unauthenticated {
+              continue;
+            }
+            if !event_session.subscriptions.wants(&event) {
+              continue;
+            }
+            if event_tx.send(("event".to_string(), encoding.event(&event))).await.is_err() {
               break;
             }
-          },
-          _ => {
-            tokio::spawn(async move {
-              let response = handle_request(request, params_sender, game_data).await;
-              if let Err(e) = response_tx.send((request_id, response)).await {
-                eprintln!("Error sending response: {}", e);
-              }
-            });
           }
+          Err(broadcast::error::RecvError::Lagged(skipped)) => metrics::events_dropped(skipped),
+          Err(broadcast::error::RecvError::Closed) => break,
         }
       }
+    });
+
+    // Requests are dispatched by a separate task so that the connection can
+    // still be read while a mutating request is being handled, which is needed
+    // to cancel it
+    let (job_tx, mut job_rx) = mpsc::channel::<Job>(32);
+    let dispatch_session = session.clone();
+    let dispatch_tx = response_tx.clone();
+    let dispatch_handle = tokio::spawn(async move {
+      let mut concurrent = JoinSet::new();
+      while let Some(job) = job_rx.recv().await {
+        while concurrent.try_join_next().is_some() {}
+        if job.request.payload.is_mutating() {
+          run_job(job, &dispatch_session, encoding, &dispatch_tx).await;
+        } else {
+          let session = dispatch_session.clone();
+          let response_tx = dispatch_tx.clone();
+          concurrent.spawn(async move {
+            run_job(job, &session, encoding, &response_tx).await;
+          });
+        }
+      }
+      while concurrent.join_next().await.is_some() {}
+    });
+
+    Self {
+      session,
+      config,
+      encoding,
+      response_tx,
+      job_tx,
+      event_handle,
+      dispatch_handle,
+      rpc_batches: Mutex::default(),
     }
   }
 
-  drop(response_tx);
-  let _ = write_handle.await;
+  /// Sends an error that isn't the response to a parsed request. Returns false
+  /// if the connection was closed.
+  async fn send_error(&self, response: Response) -> bool {
+    let message = self.encoding.response(response, &Attachments::default());
+    if let Err(e) = self.response_tx.send(("error".to_string(), message)).await {
+      warn!("Error sending error response: {}", e);
+      return false;
+    }
+    true
+  }
+
+  /// Parses a request and queues it to be handled. Returns false if the
+  /// connection was closed.
+  async fn handle_message(&self, text: String, attachments: Attachments) -> bool {
+    let encoding = self.encoding;
+    let parsed = if encoding.jsonrpc {
+      match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(serde_json::Value::Array(requests)) => return self.handle_rpc_batch(requests, attachments).await,
+        Ok(value) => parse_rpc_request(value).map_err(|response| response.map(|r| serde_json::to_string(&r).unwrap())),
+        Err(e) => Err(Some(serde_json::to_string(&rpc_parse_error(e)).unwrap())),
+      }
+    } else {
+      parse_request(&text)
+        .map(|request| (request, true))
+        .map_err(|response| Some(serde_json::to_string(&response).unwrap()))
+    };
+    let (request, respond) = match parsed {
+      Ok(parsed) => parsed,
+      Err(None) => return true,
+      Err(Some(text)) => {
+        let message = encoding.message(text, &attachments);
+        if let Err(e) = self.response_tx.send(("error".to_string(), message)).await {
+          warn!("Error sending error response: {}", e);
+          return false;
+        }
+        return true;
+      }
+    };
+    let reply_to = if respond { ReplyTo::Client } else { ReplyTo::Nobody };
+    self.submit(request, attachments, reply_to).await
+  }
+
+  /// Handles the requests in a JSON-RPC batch like separate requests, and
+  /// sends their responses together in an array once all of them have
+  /// finished. Notifications are left out of the array, and nothing is sent
+  /// if the batch only contains notifications.
+  async fn handle_rpc_batch(&self, requests: Vec<serde_json::Value>, attachments: Attachments) -> bool {
+    if requests.is_empty() {
+      metrics::invalid_request(ErrorCode::InvalidRequest);
+      let response = RpcResponse::error(
+        serde_json::Value::Null,
+        RpcError::new(RPC_INVALID_REQUEST, "JSON-RPC batches can't be empty."),
+      );
+      let message = Message::Text(serde_json::to_string(&response).unwrap());
+      return self.response_tx.send(("error".to_string(), message)).await.is_ok();
+    }
+
+    let mut replies: Vec<BoxFuture<'static, Option<RpcResponse>>> = Vec::new();
+    for value in requests {
+      // Every response in the batch is sent in the same text frame, so FXRs
+      // are sent back as base64
+      let attachments = Attachments { received: attachments.received.clone(), ..Attachments::default() };
+      match parse_rpc_request(value) {
+        Ok((request, true)) => {
+          let (reply_tx, reply_rx) = oneshot::channel();
+          if !self.submit(request, attachments, ReplyTo::Batch(reply_tx)).await {
+            return false;
+          }
+          replies.push(reply_rx.map(|response| response.ok().map(RpcResponse::from)).boxed());
+        }
+        Ok((request, false)) => {
+          if !self.submit(request, attachments, ReplyTo::Nobody).await {
+            return false;
+          }
+        }
+        Err(response) => replies.push(future::ready(response.map(|response| *response)).boxed()),
+      }
+    }
+    if replies.is_empty() {
+      return true;
+    }
+
+    let response_tx = self.response_tx.clone();
+    let mut rpc_batches = self.rpc_batches.lock().unwrap();
+    while rpc_batches.try_join_next().is_some() {}
+    rpc_batches.spawn(async move {
+      let responses: Vec<RpcResponse> = future::join_all(replies).await.into_iter().flatten().collect();
+      let message = Message::Text(serde_json::to_string(&responses).unwrap());
+      if let Err(e) = response_tx.send(("batch".to_string(), message)).await {
+        warn!("Error sending response: {}", e);
+      }
+    });
+    true
+  }
+
+  /// Handles a parsed request, or queues it to be handled. Returns false if
+  /// the connection was closed.
+  async fn submit(&self, request: Request, attachments: Attachments, reply_to: ReplyTo) -> bool {
+    let encoding = self.encoding;
+
+    // Authentication and permissions are handled here so that requests
+    // received after an auth request are always checked with the new role
+    let started = Instant::now();
+    let span = logging::request_span(self.session.client_id, &request.request_id, request.payload.type_name());
+    let role = *self.session.role.lock().unwrap();
+    let response = span.in_scope(|| match &request.payload {
+      RequestPayload::Auth { token } => {
+        Some(authenticate(&self.session, &self.config.auth, request.request_id.clone(), token))
+      }
+      payload if !role.allows(payload) => Some(permission_denied(&request, role)),
+      // Without auth tokens every client is read-write, so anything that can
+      // connect could otherwise stop the server
+      RequestPayload::Shutdown if !self.config.auth.required() => Some(shutdown_without_auth(&request)),
+      // Shutdown requests are responded to before shutdown starts, so that
+      // the response is sent before the connection is closed
+      RequestPayload::Shutdown => {
+        info!("Client {} requested a shutdown", self.session.client_id);
+        Some(Response {
+          request_id: request.request_id.clone(),
+          success: true,
+          message: "Shutting down".to_string(),
+          data: None,
+          error: None,
+        })
+      }
+      _ => None,
+    });
+    if let Some(response) = response {
+      metrics::request(request.payload.type_name(), started.elapsed(), response.error.as_ref().map(|error| error.code));
+      logging::request_finished(&span, &response, started.elapsed());
+      let shut_down = response.success && matches!(request.payload, RequestPayload::Shutdown);
+      if !send_response(response, &attachments, reply_to, encoding, &self.response_tx).await {
+        return false;
+      }
+      if shut_down {
+        shutdown::start();
+      }
+      return true;
+    }
+
+    let deadline = request.timeout_ms
+      .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms));
+
+    // Cancel requests skip the queue so they don't wait for the request they
+    // are cancelling
+    if let RequestPayload::Cancel { .. } = request.payload {
+      let job = Job { request, attachments, reply_to, deadline, cancelled: None };
+      let session = self.session.clone();
+      let response_tx = self.response_tx.clone();
+      tokio::spawn(async move {
+        run_job(job, &session, encoding, &response_tx).await;
+      });
+      return true;
+    }
+
+    let started = self.session.in_flight.lock().unwrap()
+      .start(reply_to.responds().then_some(request.request_id.as_str()));
+    let Some(cancelled) = started else {
+      warn!("Request {} is already in progress", request.request_id);
+      let response = Response {
+        request_id: request.request_id.clone(),
+        success: false,
+        message: format!("A request with the ID {} is already in progress", request.request_id),
+        data: None,
+        error: Some(ResponseError {
+          param: Some("request_id".to_string()),
+          ..ResponseError::new(ErrorCode::InvalidRequest)
+        }),
+      };
+      return send_response(response, &attachments, reply_to, encoding, &self.response_tx).await;
+    };
+    let job = Job { request, attachments, reply_to, deadline, cancelled: Some(cancelled) };
+    self.job_tx.send(job).await.is_ok()
+  }
+
+  /// Stops forwarding events and waits for the requests that are waiting or
+  /// being handled.
+  async fn finish(self) {
+    self.event_handle.abort();
+    drop(self.job_tx);
+    let _ = self.dispatch_handle.await;
+    let mut rpc_batches = self.rpc_batches.into_inner().unwrap();
+    while rpc_batches.join_next().await.is_some() {}
+  }
+}
+
+/// Handles a request unless it is cancelled or times out first, and sends the
+/// response.
+async fn run_job(job: Job, session: &Session, encoding: Encoding, response_tx: &mpsc::Sender<(String, Message)>) {
+  let Job { request, mut attachments, reply_to, deadline, cancelled } = job;
+  let request_id = request.request_id.clone();
+  let timeout_ms = request.timeout_ms;
+  let type_name = request.payload.type_name();
+  let started = Instant::now();
+  let span = logging::request_span(session.client_id, &request_id, type_name);
+  let job_id = cancelled.as_ref().map(|(job_id, _)| *job_id);
+  let timed_out = sleep_until_deadline(deadline);
+  let cancelled = async {
+    if let Some((_, receiver)) = cancelled {
+      if receiver.await.is_ok() {
+        return;
+      }
+    }
+    std::future::pending().await
+  };
+
+  let response = async {
+    // Cancellation and the deadline are checked first, since handlers like
+    // reload_fxrs change the game as soon as they are polled. Otherwise a
+    // request that was cancelled or timed out while it was queued could
+    // still change it.
+    tokio::select! {
+      biased;
+      _ = cancelled => {
+        info!("Cancelled request {}", request_id);
+        attachments.to_send.clear();
+        Response {
+          request_id: request_id.clone(),
+          success: false,
+          message: "Request was cancelled".to_string(),
+          data: None,
+          error: Some(ResponseError::new(ErrorCode::Cancelled)),
+        }
+      }
+      _ = timed_out => {
+        warn!("Request {} timed out", request_id);
+        attachments.to_send.clear();
+        Response {
+          request_id: request_id.clone(),
+          success: false,
+          message: format!("Request timed out after {} ms", timeout_ms.unwrap_or_default()),
+          data: None,
+          error: Some(ResponseError::new(ErrorCode::TimedOut)),
+        }
+      }
+      response = handle_request(request, &mut attachments, session) => response,
+    }
+  }.instrument(span.clone()).await;
+  if let Some(job_id) = job_id {
+    session.in_flight.lock().unwrap().finish(job_id, &request_id);
+  }
+  metrics::request(type_name, started.elapsed(), response.error.as_ref().map(|error| error.code));
+  logging::request_finished(&span, &response, started.elapsed());
+
+  send_response(response, &attachments, reply_to, encoding, response_tx).await;
+}
+
+/// Sends a response where it should go. Returns false if the connection was
+/// closed.
+async fn send_response(
+  response: Response,
+  attachments: &Attachments,
+  reply_to: ReplyTo,
+  encoding: Encoding,
+  response_tx: &mpsc::Sender<(String, Message)>,
+) -> bool {
+  match reply_to {
+    ReplyTo::Client => {
+      let request_id = response.request_id.clone();
+      if let Err(e) = response_tx.send((request_id, encoding.response(response, attachments))).await {
+        warn!("Error sending response: {}", e);
+        return false;
+      }
+      true
+    }
+    // The batch is dropped if the connection closes
+    ReplyTo::Batch(batch) => {
+      let _ = batch.send(response);
+      true
+    }
+    ReplyTo::Nobody => true,
+  }
+}
+
+/// Handles an auth request by changing the role of the connection if the
+/// token is valid.
+fn authenticate(session: &Session, auth: &auth::AuthConfig, request_id: String, token: &str) -> Response {
+  let Some(role) = auth.role_for_token(token) else {
+    warn!("Client {} sent an invalid auth token", session.client_id);
+    return Response {
+      request_id,
+      success: false,
+      message: "Invalid auth token".to_string(),
+      data: None,
+      error: Some(ResponseError {
+        param: Some("token".to_string()),
+        ..ResponseError::new(ErrorCode::Unauthenticated)
+      }),
+    };
+  };
+  *session.role.lock().unwrap() = role;
+  info!("Client {} authenticated with the {:?} role", session.client_id, role);
+  Response {
+    request_id,
+    success: true,
+    message: "Successfully authenticated".to_string(),
+    data: Some(ResponseData::Auth { role }),
+    error: None,
+  }
+}
+
+fn permission_denied(request: &Request, role: Role) -> Response {
+  let request_type = request.payload.type_name();
+  let (message, code) = if role == Role::Unauthenticated {
+    (format!("Authenticate with an auth request before using {}", request_type), ErrorCode::Unauthenticated)
+  } else {
+    (format!("Read-only clients can't use {}", request_type), ErrorCode::PermissionDenied)
+  };
+  warn!("{}", message);
+  Response {
+    request_id: request.request_id.clone(),
+    success: false,
+    message,
+    data: None,
+    error: Some(ResponseError {
+      request_type: Some(request_type.to_string()),
+      ..ResponseError::new(code)
+    }),
+  }
+}
+
+fn shutdown_without_auth(request: &Request) -> Response {
+  let message = "shutdown can only be used when auth_tokens are configured".to_string();
+  warn!("{}", message);
+  Response {
+    request_id: request.request_id.clone(),
+    success: false,
+    message,
+    data: None,
+    error: Some(ResponseError {
+      request_type: Some(request.payload.type_name().to_string()),
+      ..ResponseError::new(ErrorCode::PermissionDenied)
+    }),
+  }
+}
+
+/// Waits until the deadline, or forever if there is none.
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+  match deadline {
+    Some(deadline) => tokio::time::sleep_until(deadline).await,
+    None => std::future::pending().await,
+  }
+}
+
+/// Asks the writer to close the connection.
+async fn close(response_tx: &mpsc::Sender<(String, Message)>, code: CloseCode, reason: &str) {
+  let frame = CloseFrame { code, reason: reason.to_string().into() };
+  let _ = response_tx.send(("close".to_string(), Message::Close(Some(frame)))).await;
+}
+
+/// Finds the request ID in a request that could not be parsed, so that the
+/// client can still tell which request the error is about.
+fn find_request_id(text: &str) -> Option<String> {
+  let start = text.find("\"request_id\"")? + "\"request_id\"".len();
+  let rest = text[start..].trim_start().strip_prefix(':')?;
+  let value = serde_json::Deserializer::from_str(rest).into_iter::<serde_json::Value>().next()?.ok()?;
+  request_id_text(&value)
+}
+
+/// Request IDs should be strings, but the text of numbers is accepted when
+/// responding to invalid requests.
+fn request_id_text(value: &serde_json::Value) -> Option<String> {
+  match value {
+    serde_json::Value::String(request_id) => Some(request_id.clone()),
+    serde_json::Value::Number(request_id) => Some(request_id.to_string()),
+    _ => None,
+  }
+}
+
+/// Parses the text of a request, or returns the error response to send.
+/// Responses are boxed since they are much larger than requests.
+fn parse_request(text: &str) -> Result<Request, Box<Response>> {
+  match serde_json::from_str::<serde_json::Value>(text) {
+    Ok(value) => parse_request_value(value),
+    Err(e) => {
+      metrics::invalid_request(ErrorCode::InvalidRequest);
+      Err(Box::new(Response {
+        request_id: find_request_id(text).unwrap_or_else(|| ":ERROR:".into()),
+        success: false,
+        message: format!("Invalid request format: {}", e),
+        data: None,
+        error: Some(ResponseError::new(ErrorCode::InvalidRequest)),
+      }))
+    }
+  }
+}
+
+fn rpc_parse_error(e: serde_json::Error) -> RpcResponse {
+  metrics::invalid_request(ErrorCode::InvalidRequest);
+  RpcResponse::error(serde_json::Value::Null, RpcError::new(RPC_PARSE_ERROR, format!("Invalid JSON: {}", e)))
+}
+
+/// Parses a JSON-RPC request. The returned bool is false for notifications,
+/// which are not responded to, not even if they are invalid. Error responses
+/// are boxed since they are much larger than requests.
+fn parse_rpc_request(value: serde_json::Value) -> Result<(Request, bool), Option<Box<RpcResponse>>> {
+  let id = value.get("id").cloned().unwrap_or(serde_json::Value::Null);
+  let rpc_request = serde_json::from_value::<RpcRequest>(value).map_err(|e| {
+    metrics::invalid_request(ErrorCode::InvalidRequest);
+    let error = RpcError::new(RPC_INVALID_REQUEST, format!("Invalid JSON-RPC request: {}", e));
+    Some(Box::new(RpcResponse::error(id.clone(), error)))
+  })?;
+  let is_notification = rpc_request.id.is_none();
+  rpc_request.to_request_value()
+    .map_err(|e| {
+      metrics::invalid_request(ErrorCode::InvalidRequest);
+      Box::new(RpcResponse::error(id, e))
+    })
+    .and_then(|value| parse_request_value(value).map_err(|response| Box::new(RpcResponse::from(*response))))
+    .map(|request| (request, !is_notification))
+    .map_err(|response| (!is_notification).then_some(response))
+}
+
+fn parse_request_value(value: serde_json::Value) -> Result<Request, Box<Response>> {
+  let request_id = value.get("request_id").and_then(request_id_text);
+  let request_type = value.get("type").and_then(|v| v.as_str()).map(str::to_string);
+  match Request::from_value(value) {
+    Ok(request) => Ok(request),
+    Err(e) => {
+      let (message, code) = match &request_type {
+        Some(t) if REQUEST_TYPE_NAMES.contains(&t.as_str()) => {
+          let code = if e.param.is_some() { ErrorCode::InvalidParam } else { ErrorCode::InvalidRequest };
+          (format!("Invalid request format: {}", e), code)
+        }
+        _ => (
+          format!("Invalid request type. Valid types are: {}", REQUEST_TYPE_NAMES.join(", ")),
+          ErrorCode::UnknownRequestType,
+        ),
+      };
+      warn!("{}", message);
+      metrics::invalid_request(code);
+      Err(Box::new(Response {
+        request_id: request_id.unwrap_or_else(|| ":ERROR:".to_string()),
+        success: false,
+        message,
+        data: None,
+        error: Some(ResponseError {
+          param: e.param,
+          request_type,
+          ..ResponseError::new(code)
+        }),
+      }))
+    }
+  }
 }
 
 async fn handle_request(
   request: Request,
-  params_sender: mpsc::Sender<ParamsRequestType>,
-  game_data: GameData
+  attachments: &mut Attachments,
+  session: &Session,
 ) -> Response {
-  match request.request_type {
-    RequestType::ReloadFXRs => {
+  let game_data = session.game_data;
+  let request_type = request.payload.type_name();
+  match request.payload {
+    RequestPayload::ReloadFxrs { fxrs } => {
       if !game_data.features.reload {
-        eprintln!("FXR reloading is not supported in {}", game_data.name);
+        warn!("FXR reloading is not supported in {}", game_data.name);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("FXR reloading is not supported in {}", game_data.name),
           data: None,
+          error: Some(ResponseError {
+            request_type: Some(request_type.to_string()),
+            ..ResponseError::new(ErrorCode::UnsupportedOperation)
+          }),
         };
       }
-      if let Some(fxrs) = request.params.get("fxrs") {
-        if let Some(fxr_array) = fxrs.as_array() {
-          let mut fxr_bytes: Vec<Vec<u8>> = Vec::new();
-          for fxr in fxr_array {
-            if let Some(base64_str) = fxr.as_str() {
-              match general_purpose::STANDARD.decode(base64_str) {
-                Ok(bytes) => fxr_bytes.push(bytes),
-                Err(e) => {
-                  eprintln!("Failed to decode base64 FXR: {}", e);
-                  return Response {
-                    request_id: request.request_id,
-                    success: false,
-                    message: format!("Failed to decode base64 FXR: {}", e),
-                    data: None,
-                  }
-                }
-              }
+      let mut fxr_bytes: Vec<Vec<u8>> = Vec::new();
+      for fxr in &fxrs {
+        match attachments.resolve(fxr) {
+          Ok(bytes) => fxr_bytes.push(bytes),
+          Err(message) => {
+            warn!("{}", message);
+            return Response {
+              request_id: request.request_id,
+              success: false,
+              message,
+              data: None,
+              error: Some(attachment_error(fxr)),
             }
           }
-          let fxr_id_opt = if fxr_bytes.len() == 1 && fxr_bytes[0].len() >= 0x10 {
-            Some(u32::from_le_bytes(fxr_bytes[0][0xc..0x10].try_into().unwrap()))
+        }
+      }
+      let fxr_ids: Vec<u32> = fxr_bytes.iter()
+        .filter_map(|fxr| fxr.get(0xc..0x10))
+        .map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+        .collect();
+      match patcher::patch(&game_data, fxr_bytes) {
+        Ok(_) => {
+          if let [id] = fxr_ids[..] {
+            info!("Reloaded FXR {}", id);
           } else {
-            None
-          };
-          match patcher::patch(&game_data, fxr_bytes) {
-            Ok(_) => {
-              if let Some(id) = fxr_id_opt {
-                println!("Reloaded FXR {}", id);
-              } else {
-                println!("Reloaded {} FXRs", fxr_array.len());
-              }
-              Response {
-                request_id: request.request_id,
-                success: true,
-                message: "Successfully reloaded FXR".to_string(),
-                data: None,
-              }
-            },
-            Err(e) => {
-              eprintln!("Failed to patch FXR: {}", e);
-              Response {
-                request_id: request.request_id,
-                success: false,
-                message: format!("Failed to patch FXR: {}", e),
-                data: None,
-              }
-            }
+            info!("Reloaded {} FXRs", fxrs.len());
           }
-        } else {
-          eprintln!("Invalid fxrs parameter: expected array");
+          metrics::fxrs_reloaded(fxrs.len());
+          events::publish(ServerEvent::FxrReloaded { ids: fxr_ids, client: session.client_id });
+          Response {
+            request_id: request.request_id,
+            success: true,
+            message: "Successfully reloaded FXR".to_string(),
+            data: None,
+            error: None,
+          }
+        },
+        Err(e) => {
+          error!("Failed to patch FXR: {}", e);
           Response {
             request_id: request.request_id,
             success: false,
-            message: "Invalid fxrs parameter: expected array".to_string(),
+            message: format!("Failed to patch FXR: {}", e),
             data: None,
+            error: Some((&e).into()),
           }
         }
-      } else {
-        eprintln!("Missing fxrs parameter");
-        Response {
-          request_id: request.request_id,
-          success: false,
-          message: "Missing fxrs parameter".to_string(),
-          data: None,
-        }
       }
     }
-    RequestType::SetResidentSFX => {
+    RequestPayload::SetResidentSfx { weapon: weapon_id, sfx: sfx_id, dmy: dmy_id } => {
       if !game_data.features.params {
-        eprintln!("Parameter modification is not supported in {}", game_data.name);
+        warn!("Parameter modification is not supported in {}", game_data.name);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("Parameter modification is not supported in {}", game_data.name),
           data: None,
+          error: Some(ResponseError {
+            request_type: Some(request_type.to_string()),
+            ..ResponseError::new(ErrorCode::UnsupportedOperation)
+          }),
         };
       }
-      let weapon_id = match request.params.get("weapon").and_then(|v| v.as_u64()) {
-        Some(id) => id as u32,
-        None => {
-          eprintln!("Missing or invalid weapon parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid weapon parameter".to_string(),
-            data: None,
-          }
-        }
-      };
-
-      let sfx_id = match request.params.get("sfx").and_then(|v| v.as_u64()) {
-        Some(id) => id as i32,
-        None => {
-          eprintln!("Missing or invalid sfx parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid sfx parameter".to_string(),
-            data: None,
-          }
-        }
-      };
-
-      let dmy_id = match request.params.get("dmy").and_then(|v| v.as_u64()) {
-        Some(id) => id as i32,
-        None => {
-          eprintln!("Missing or invalid dmy parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid dmy parameter".to_string(),
-            data: None,
-          }
-        }
-      };
 
-      if let Err(e) = params_sender.send(ParamsRequestType::SetResidentSFX { weapon_id, sfx_id, dmy_id }).await {
-        eprintln!("Failed to send params request: {}", e);
+      let (done_tx, done_rx) = oneshot::channel();
+      let params_request = ParamsRequestType::SetResidentSFX { weapon_id, sfx_id, dmy_id };
+      if let Err(e) = session.params_sender.send((params_request, done_tx)).await {
+        error!("Failed to send params request: {}", e);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("Failed to send params request: {}", e),
           data: None,
+          error: Some(ResponseError::new(ErrorCode::Internal)),
+        };
+      }
+      if done_rx.await.is_err() {
+        error!("Failed to apply params request");
+        return Response {
+          request_id: request.request_id,
+          success: false,
+          message: "Failed to apply params request".to_string(),
+          data: None,
+          error: Some(ResponseError::new(ErrorCode::Internal)),
         };
       }
 
-      println!("Set resident SFX: weapon_id={}, sfx_id={}, dmy_id={}", weapon_id, sfx_id, dmy_id);
+      info!("Set resident SFX: weapon_id={}, sfx_id={}, dmy_id={}", weapon_id, sfx_id, dmy_id);
       Response {
         request_id: request.request_id,
         success: true,
         message: format!("Successfully set resident SFX for weapon {}", weapon_id),
         data: None,
+        error: None,
       }
     }
-    RequestType::SetSpEffectSFX => {
+    RequestPayload::SetSpEffectSfx { sp_effect: sp_effect_id, sfx: sfx_id, dmy: dmy_id, vfx: target_vfx_id } => {
       if !game_data.features.params {
-        eprintln!("Parameter modification is not supported in {}", game_data.name);
+        warn!("Parameter modification is not supported in {}", game_data.name);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("Parameter modification is not supported in {}", game_data.name),
           data: None,
+          error: Some(ResponseError {
+            request_type: Some(request_type.to_string()),
+            ..ResponseError::new(ErrorCode::UnsupportedOperation)
+          }),
         };
       }
-      let sp_effect_id = match request.params.get("spEffect").and_then(|v| v.as_u64()) {
-        Some(id) => id as u32,
-        None => {
-          eprintln!("Missing or invalid spEffect parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid spEffect parameter".to_string(),
-            data: None,
-          }
-        }
-      };
 
-      let sfx_id = match request.params.get("sfx").and_then(|v| v.as_u64()) {
-        Some(id) => id as i32,
-        None => {
-          eprintln!("Missing or invalid sfx parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid sfx parameter".to_string(),
-            data: None,
-          }
-        }
-      };
-
-      let dmy_id = match request.params.get("dmy").and_then(|v| v.as_u64()) {
-        Some(id) => id as i16,
-        None => {
-          eprintln!("Missing or invalid dmy parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid dmy parameter".to_string(),
-            data: None,
-          }
-        }
-      };
-
-      let target_vfx_id = request.params.get("vfx").and_then(|v| v.as_i64()).map(|id| id as i32);
-
-      if let Err(e) = params_sender.send(ParamsRequestType::SetSpEffectSFX { 
+      let (done_tx, done_rx) = oneshot::channel();
+      let params_request = ParamsRequestType::SetSpEffectSFX { 
         sp_effect_id, 
         sfx_id, 
         dmy_id, 
         target_vfx_id 
-      }).await {
-        eprintln!("Failed to send params request: {}", e);
+      };
+      if let Err(e) = session.params_sender.send((params_request, done_tx)).await {
+        error!("Failed to send params request: {}", e);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("Failed to send params request: {}", e),
           data: None,
+          error: Some(ResponseError::new(ErrorCode::Internal)),
+        };
+      }
+      if done_rx.await.is_err() {
+        error!("Failed to apply params request");
+        return Response {
+          request_id: request.request_id,
+          success: false,
+          message: "Failed to apply params request".to_string(),
+          data: None,
+          error: Some(ResponseError::new(ErrorCode::Internal)),
         };
       }
 
-      println!("Set SpEffect SFX: sp_effect_id={}, sfx_id={}, dmy_id={}, target_vfx_id={:?}", sp_effect_id, sfx_id, dmy_id, target_vfx_id);
+      info!("Set SpEffect SFX: sp_effect_id={}, sfx_id={}, dmy_id={}, target_vfx_id={:?}", sp_effect_id, sfx_id, dmy_id, target_vfx_id);
       Response {
         request_id: request.request_id,
         success: true,
         message: format!("Successfully updated SFX for SpEffect {}", sp_effect_id),
         data: None,
+        error: None,
       }
     }
-    RequestType::GetFXR => {
+    RequestPayload::GetFxr { id: fxr_id } => {
       if !game_data.features.extract {
-        eprintln!("FXR extraction is not supported in {}", game_data.name);
+        warn!("FXR extraction is not supported in {}", game_data.name);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("FXR extraction is not supported in {}", game_data.name),
           data: None,
+          error: Some(ResponseError {
+            request_type: Some(request_type.to_string()),
+            ..ResponseError::new(ErrorCode::UnsupportedOperation)
+          }),
         };
       }
-      let fxr_id = match request.params.get("id").and_then(|v| v.as_u64()) {
-        Some(id) => id as u32,
-        None => {
-          eprintln!("Missing or invalid id parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid id parameter".to_string(),
-            data: None,
-          }
-        }
-      };
 
       let fxr_bytes = match patcher::extract(&game_data, fxr_id) {
         Ok(bytes) => bytes,
         Err(e) => {
-          eprintln!("Failed to extract FXR: {}", e);
+          error!("Failed to extract FXR: {}", e);
           return Response {
             request_id: request.request_id,
             success: false,
             message: format!("Failed to extract FXR: {}", e),
             data: None,
+            error: Some((&e).into()),
           }
         }
       };
 
-      let base64_str = general_purpose::STANDARD.encode(&fxr_bytes);
-      println!("Extracted FXR {}", fxr_id);
+      info!("Extracted FXR {}", fxr_id);
+      metrics::fxrs_extracted(1);
       Response {
         request_id: request.request_id,
         success: true,
         message: "Successfully extracted FXR".to_string(),
-        data: Some(serde_json::json!({ "fxr": base64_str })),
+        data: Some(ResponseData::Fxr { fxr: attachments.add(fxr_bytes) }),
+        error: None,
       }
     }
-    RequestType::GetFXRs => {
+    RequestPayload::GetFxrs { ids } => {
       if !game_data.features.extract {
-        eprintln!("FXR extraction is not supported in {}", game_data.name);
+        warn!("FXR extraction is not supported in {}", game_data.name);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("FXR extraction is not supported in {}", game_data.name),
           data: None,
+          error: Some(ResponseError {
+            request_type: Some(request_type.to_string()),
+            ..ResponseError::new(ErrorCode::UnsupportedOperation)
+          }),
         };
       }
-      let ids = match request.params.get("ids").and_then(|v| v.as_array()) {
-        Some(ids) => ids.iter()
-          .filter_map(|v| v.as_u64().map(|id| id as u32))
-          .collect::<Vec<_>>(),
-        None => {
-          eprintln!("Missing or invalid ids parameter");
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: "Missing or invalid ids parameter".to_string(),
-            data: None,
-          }
-        }
-      };
 
-      let fxrs = match patcher::extract_multiple(&game_data, &ids) {
-        Ok(bytes_vec) => bytes_vec,
-        Err(e) => {
-          eprintln!("Failed to extract FXRs: {}", e);
-          return Response {
-            request_id: request.request_id,
-            success: false,
-            message: format!("Failed to extract FXRs: {}", e),
-            data: None,
+      // Extracted in chunks, yielding in between so that the request can be
+      // cancelled or time out
+      let mut fxrs = Vec::with_capacity(ids.len());
+      for chunk in ids.chunks(EXTRACT_CHUNK_SIZE) {
+        match patcher::extract_multiple(&game_data, &chunk.to_vec()) {
+          Ok(bytes_vec) => fxrs.extend(bytes_vec),
+          Err(e) => {
+            error!("Failed to extract FXRs: {}", e);
+            return Response {
+              request_id: request.request_id,
+              success: false,
+              message: format!("Failed to extract FXRs: {}", e),
+              data: None,
+              error: Some((&e).into()),
+            }
           }
         }
-      };
+        tokio::task::yield_now().await;
+      }
 
-      let base64_fxrs: Vec<Option<String>> = fxrs.into_iter()
-        .map(|maybe_bytes| maybe_bytes.map(|bytes| general_purpose::STANDARD.encode(&bytes)))
+      let fxrs: Vec<Option<FxrData>> = fxrs.into_iter()
+        .map(|maybe_bytes| maybe_bytes.map(|bytes| attachments.add(bytes)))
         .collect();
 
       if ids.len() == 1 {
-        println!("Extracted FXR {}", ids[0]);
+        info!("Extracted FXR {}", ids[0]);
       } else {
-        println!("Extracted {} FXRs", base64_fxrs.len());
+        info!("Extracted {} FXRs", fxrs.len());
       }
+      metrics::fxrs_extracted(fxrs.iter().flatten().count());
       Response {
         request_id: request.request_id,
         success: true,
         message: "Successfully extracted FXRs".to_string(),
-        data: Some(serde_json::json!({ "fxrs": base64_fxrs })),
+        data: Some(ResponseData::Fxrs { fxrs }),
+        error: None,
       }
     }
-    RequestType::ListFXRs => {
+    RequestPayload::ListFxrs => {
       if !game_data.features.extract {
-        eprintln!("FXR listing is not supported in {}", game_data.name);
+        warn!("FXR listing is not supported in {}", game_data.name);
         return Response {
           request_id: request.request_id,
           success: false,
           message: format!("FXR listing is not supported in {}", game_data.name),
           data: None,
+          error: Some(ResponseError {
+            request_type: Some(request_type.to_string()),
+            ..ResponseError::new(ErrorCode::UnsupportedOperation)
+          }),
         };
       }
       let fxr_ids = match patcher::list_ids(&game_data) {
         Ok(ids) => ids,
         Err(e) => {
-          eprintln!("Failed to list FXRs: {}", e);
+          error!("Failed to list FXRs: {}", e);
           return Response {
             request_id: request.request_id,
             success: false,
             message: format!("Failed to list FXRs: {}", e),
             data: None,
+            error: Some((&e).into()),
           }
         }
       };
 
-      println!("Listed {} FXR(s)", fxr_ids.len());
+      debug!("Listed {} FXR(s)", fxr_ids.len());
       Response {
         request_id: request.request_id,
         success: true,
         message: "Successfully listed FXRs".to_string(),
-        data: Some(serde_json::json!({ "fxrs": fxr_ids })),
+        data: Some(ResponseData::FxrIds { ids: fxr_ids }),
+        error: None,
+      }
+    }
+    RequestPayload::GetCapabilities => {
+      let requests = request_types().into_iter()
+        .filter(|request| game_data.features.supports_request(&request.name))
+        .collect();
+      let mut features = game_data.features.feature_names();
+      if session.binary_frames {
+        features.extend([feature::BINARY_FRAMES, feature::ZSTD].map(String::from));
+      }
+      features.extend([feature::CONCURRENT_REQUESTS, feature::EVENTS].map(String::from));
+      let negotiated = if session.zstd { vec![feature::ZSTD.to_string()] } else { Vec::new() };
+      Response {
+        request_id: request.request_id,
+        success: true,
+        message: "Successfully got capabilities".to_string(),
+        data: Some(ResponseData::Capabilities {
+          capabilities: Capabilities {
+            protocol_version: PROTOCOL_VERSION,
+            requests,
+            features,
+            negotiated,
+          },
+        }),
+        error: None,
+      }
+    }
+    RequestPayload::Batch { requests, stop_on_error } => {
+      let stop_on_error = stop_on_error.unwrap_or(false);
+      if stop_on_error {
+        // Fail before changing anything if a request is bound to fail
+        for (step, payload) in requests.iter().enumerate() {
+          if let Err((message, error)) = check_batch_request(payload, attachments, &game_data) {
+            warn!("Batch request {} is invalid: {}", step, message);
+            return Response {
+              request_id: request.request_id,
+              success: false,
+              message: format!("Batch request {} is invalid: {}", step, message),
+              data: None,
+              error: Some(ResponseError { step: Some(step as u32), ..error }),
+            };
+          }
+        }
+      }
+
+      let count = requests.len();
+      let mut results = Vec::new();
+      let mut failure = None;
+      for (step, payload) in requests.into_iter().enumerate() {
+        let response = if let RequestPayload::Batch { .. } = payload {
+          Response {
+            request_id: request.request_id.clone(),
+            success: false,
+            message: "Batches can't be nested".to_string(),
+            data: None,
+            error: Some(ResponseError {
+              request_type: Some("batch".to_string()),
+              ..ResponseError::new(ErrorCode::InvalidRequest)
+            }),
+          }
+        } else {
+          let step_request = Request { request_id: request.request_id.clone(), timeout_ms: None, payload };
+          Box::pin(handle_request(step_request, attachments, session)).await
+        };
+        let failed = !response.success;
+        if failed && failure.is_none() {
+          failure = Some((step, response.message.clone(), response.error.clone()));
+        }
+        results.push(response.into());
+        if failed && stop_on_error {
+          break;
+        }
+      }
+
+      let data = Some(ResponseData::Batch { results });
+      match failure {
+        None => {
+          info!("Ran batch of {} request(s)", count);
+          Response {
+            request_id: request.request_id,
+            success: true,
+            message: format!("Successfully ran {} request(s)", count),
+            data,
+            error: None,
+          }
+        }
+        Some((step, message, error)) => {
+          warn!("Batch request {} failed: {}", step, message);
+          Response {
+            request_id: request.request_id,
+            success: false,
+            message: format!("Batch request {} failed: {}", step, message),
+            data,
+            error: Some(ResponseError {
+              step: Some(step as u32),
+              ..error.unwrap_or_else(|| ResponseError::new(ErrorCode::Internal))
+            }),
+          }
+        }
+      }
+    }
+    RequestPayload::Subscribe { events } => {
+      if events.contains(&EventTopic::FxrsChanged) && !game_data.features.extract {
+        warn!("FXR listing is not supported in {}", game_data.name);
+        return Response {
+          request_id: request.request_id,
+          success: false,
+          message: format!("FXR listing is not supported in {}", game_data.name),
+          data: None,
+          error: Some(ResponseError {
+            request_type: Some(request_type.to_string()),
+            ..ResponseError::new(ErrorCode::UnsupportedOperation)
+          }),
+        };
+      }
+      for topic in &events {
+        session.subscriptions.subscribe(*topic);
+      }
+      debug!("Client subscribed to {:?}", events);
+      Response {
+        request_id: request.request_id,
+        success: true,
+        message: "Successfully subscribed to events".to_string(),
+        data: None,
+        error: None,
       }
     }
-    RequestType::Unknown => {
-      eprintln!("Invalid request type. Valid types are: {}", REQUEST_TYPE_NAMES.join(", "));
+    RequestPayload::SubscribeLogs { level } => {
+      let level = level.unwrap_or(LogLevel::Info);
+      session.subscriptions.subscribe_logs(level);
+      debug!("Client subscribed to logs at {:?} level", level);
+      Response {
+        request_id: request.request_id,
+        success: true,
+        message: "Successfully subscribed to logs".to_string(),
+        data: None,
+        error: None,
+      }
+    }
+    RequestPayload::Unsubscribe { events } => {
+      for topic in &events {
+        session.subscriptions.unsubscribe(*topic);
+      }
+      debug!("Client unsubscribed from {:?}", events);
+      Response {
+        request_id: request.request_id,
+        success: true,
+        message: "Successfully unsubscribed from events".to_string(),
+        data: None,
+        error: None,
+      }
+    }
+    // Auth and shutdown requests are handled as soon as they are received, so
+    // this is only reached from batches
+    RequestPayload::Auth { .. } | RequestPayload::Shutdown => {
+      warn!("{} can't be used in a batch", request_type);
       Response {
         request_id: request.request_id,
         success: false,
-        message: format!("Invalid request type. Valid types are: {}", REQUEST_TYPE_NAMES.join(", ")),
+        message: format!("{} can't be used in a batch", request_type),
         data: None,
+        error: Some(ResponseError {
+          request_type: Some(request_type.to_string()),
+          ..ResponseError::new(ErrorCode::InvalidRequest)
+        }),
       }
     }
+    RequestPayload::Cancel { target_request_id } => {
+      if !session.in_flight.lock().unwrap().cancel(&target_request_id) {
+        warn!("Cannot cancel request {} because it is not in progress", target_request_id);
+        return Response {
+          request_id: request.request_id,
+          success: false,
+          message: format!("Request {} is not in progress", target_request_id),
+          data: None,
+          error: Some(ResponseError {
+            param: Some("target_request_id".to_string()),
+            ..ResponseError::new(ErrorCode::RequestNotFound)
+          }),
+        };
+      }
+      Response {
+        request_id: request.request_id,
+        success: true,
+        message: format!("Successfully cancelled request {}", target_request_id),
+        data: None,
+        error: None,
+      }
+    }
+    RequestPayload::GetStats => Response {
+      request_id: request.request_id,
+      success: true,
+      message: "Successfully got stats".to_string(),
+      data: Some(ResponseData::Stats { stats: metrics::stats() }),
+      error: None,
+    },
   }
 }
 
-async fn game_param_handler(mut rx: mpsc::Receiver<ParamsRequestType>, _tx: mpsc::Sender<Response>) {
-  while let Some(request) = rx.recv().await {
+/// Checks whether a request in a batch can be run, without running it.
+fn check_batch_request(
+  payload: &RequestPayload,
+  attachments: &Attachments,
+  game_data: &GameData,
+) -> Result<(), (String, ResponseError)> {
+  let request_type = payload.type_name();
+  if let RequestPayload::Batch { .. } = payload {
+    return Err((
+      "Batches can't be nested".to_string(),
+      ResponseError {
+        request_type: Some(request_type.to_string()),
+        ..ResponseError::new(ErrorCode::InvalidRequest)
+      },
+    ));
+  }
+  if let RequestPayload::Auth { .. } | RequestPayload::Shutdown = payload {
+    return Err((
+      format!("{} can't be used in a batch", request_type),
+      ResponseError {
+        request_type: Some(request_type.to_string()),
+        ..ResponseError::new(ErrorCode::InvalidRequest)
+      },
+    ));
+  }
+  if !game_data.features.supports_request(request_type) {
+    return Err((
+      format!("{} is not supported in {}", request_type, game_data.name),
+      ResponseError {
+        request_type: Some(request_type.to_string()),
+        ..ResponseError::new(ErrorCode::UnsupportedOperation)
+      },
+    ));
+  }
+  if let RequestPayload::ReloadFxrs { fxrs } = payload {
+    for fxr in fxrs {
+      if let Err(message) = attachments.resolve(fxr) {
+        return Err((message, attachment_error(fxr)));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Applies params requests in the order they were queued. Once `stop` is
+/// sent, no more requests can be queued and the handler returns after
+/// applying the ones that are left.
+async fn game_param_handler(mut rx: mpsc::Receiver<ParamsJob>, _tx: mpsc::Sender<Response>, stop: oneshot::Receiver<()>) {
+  tokio::pin!(stop);
+  loop {
+    let job = tokio::select! {
+      job = rx.recv() => job,
+      _ = &mut stop, if !rx.is_closed() => {
+        rx.close();
+        continue;
+      }
+    };
+    let Some((request, done)) = job else {
+      break;
+    };
+    // The request was cancelled or timed out while waiting. Requests that
+    // have started are always finished so params aren't left at -1
+    if done.is_closed() {
+      continue;
+    }
     match request {
       ParamsRequestType::SetResidentSFX { weapon_id, sfx_id, dmy_id } => {
         let param_repo = unsafe { singleton::get_instance::<FD4ParamRepository>() }
@@ -702,5 +1836,71 @@ async fn game_param_handler(mut rx: mpsc::Receiver<ParamsRequestType>, _tx: mpsc
         }
       }
     }
+    let _ = done.send(());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use protocol::SupportedFeatures;
+  use super::*;
+
+  fn test_session(params_sender: mpsc::Sender<ParamsJob>) -> Session {
+    Session {
+      client_id: 1,
+      game_data: GameData {
+        name: "Test",
+        product_name: "Test",
+        exe_names: &[],
+        features: SupportedFeatures { reload: true, params: true, extract: true },
+      },
+      params_sender,
+      subscriptions: events::Subscriptions::default(),
+      role: Mutex::new(Role::ReadWrite),
+      in_flight: Mutex::default(),
+      binary_frames: false,
+      zstd: false,
+    }
+  }
+
+  fn request(request_id: &str, payload: RequestPayload) -> Request {
+    Request { request_id: request_id.to_string(), timeout_ms: None, payload }
+  }
+
+  /// Waits for the response to a request.
+  async fn response(response_rx: &mut mpsc::Receiver<(String, Message)>, request_id: &str) -> Response {
+    loop {
+      let (id, message) = response_rx.recv().await.unwrap();
+      if id == request_id {
+        return serde_json::from_str(message.to_text().unwrap()).unwrap();
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn cancelled_requests_never_run() {
+    // select! would otherwise pick a branch at random, so a few attempts are
+    // needed for the test to fail reliably without the fix
+    for _ in 0..20 {
+      let (params_tx, mut params_rx) = mpsc::channel(8);
+      let (response_tx, mut response_rx) = mpsc::channel(32);
+      let connection = Connection::start(test_session(params_tx), Encoding::default(), Arc::default(), response_tx);
+      let set_sfx = || RequestPayload::SetResidentSfx { weapon: 1, sfx: 2, dmy: 3 };
+
+      assert!(connection.submit(request("first", set_sfx()), Attachments::default(), ReplyTo::Client).await);
+      // The first request is being applied until this is answered
+      let (_, applied) = params_rx.recv().await.unwrap();
+      assert!(connection.submit(request("second", set_sfx()), Attachments::default(), ReplyTo::Client).await);
+      let cancel = RequestPayload::Cancel { target_request_id: "second".to_string() };
+      assert!(connection.submit(request("cancel", cancel), Attachments::default(), ReplyTo::Client).await);
+      assert!(response(&mut response_rx, "cancel").await.success);
+
+      applied.send(()).unwrap();
+      assert!(response(&mut response_rx, "first").await.success);
+      let second = response(&mut response_rx, "second").await;
+      assert_eq!(second.error.map(|error| error.code), Some(ErrorCode::Cancelled));
+      assert!(params_rx.try_recv().is_err(), "the cancelled request was applied");
+      connection.finish().await;
+    }
   }
 }
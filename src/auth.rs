@@ -0,0 +1,22 @@
+use percent_encoding::percent_decode_str;
+use tokio_tungstenite::tungstenite::handshake::server::Request as HandshakeRequest;
+
+pub use settings::AuthConfig;
+
+/// Gets the token a client sent during the handshake, either as a bearer
+/// token in the `Authorization` header or in the `token` query parameter for
+/// clients that can't set headers, like browsers. Query parameters are
+/// percent-decoded, but `+` is kept as is, since tokens are more likely to
+/// contain a `+` than a space.
+pub fn handshake_token(request: &HandshakeRequest) -> Option<String> {
+  let header = request.headers().get("Authorization")
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "));
+  if let Some(token) = header {
+    return Some(token.trim().to_string());
+  }
+  request.uri().query()?
+    .split('&')
+    .find_map(|param| param.strip_prefix("token="))
+    .map(|token| percent_decode_str(token).decode_utf8_lossy().into_owned())
+}
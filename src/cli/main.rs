@@ -0,0 +1,386 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use client::{Client, ClientError, ClientOptions};
+use protocol::{Discovery, EventTopic, LogLevel, ServerEvent};
+use watch::{Respawn, WatchOptions};
+
+mod watch;
+
+/// How many FXRs to request at a time when extracting all of them.
+const EXTRACT_ALL_CHUNK_SIZE: usize = 256;
+
+#[derive(Parser)]
+#[command(name = "fxr-reload", version, about = "Controls a running FXR WebSocket reloader")]
+struct Cli {
+  /// The URL of the reloader's WebSocket server. Defaults to the most
+  /// recently started server that wrote a discovery file and can be
+  /// connected to, or the default port if there are none
+  #[arg(long, global = true)]
+  url: Option<String>,
+  /// Send FXRs as raw bytes and compress them with zstd. Makes extracting
+  /// many FXRs much faster, but requires a server with compression support
+  #[arg(long, global = true)]
+  compress: bool,
+  /// Give up on requests that take longer than this many milliseconds
+  #[arg(long, global = true)]
+  timeout: Option<u64>,
+  /// The token to authenticate with, if the server requires one
+  #[arg(long, global = true)]
+  token: Option<String>,
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Reload one or more FXR files
+  Reload {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+  },
+  /// Extract a loaded FXR from the game
+  Extract {
+    id: u32,
+    /// Where to write the FXR. Defaults to f<id>.fxr in the current directory
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+  },
+  /// Extract every loaded FXR from the game into a directory
+  ExtractAll {
+    dir: PathBuf,
+  },
+  /// List the IDs of all loaded FXRs
+  List,
+  /// Show information about the server and the game it is running in
+  Info,
+  /// Print the IDs of FXRs as they are loaded and unloaded by the game, or
+  /// reloaded by other clients
+  Monitor,
+  /// Print the server's log records as they are logged
+  Logs {
+    /// The lowest level to print: error, warn, info, debug or trace
+    #[arg(long, default_value = "info", value_parser = parse_level)]
+    level: LogLevel,
+  },
+  /// Print counters and request latencies collected by the server
+  Stats,
+  /// Stop the server, for example before unloading the DLL
+  Shutdown,
+  /// Set the resident SFX of a weapon, respawning the effect
+  SetResidentSfx {
+    weapon: u32,
+    #[arg(allow_negative_numbers = true)]
+    sfx: i32,
+    #[arg(allow_negative_numbers = true)]
+    dmy: i32,
+  },
+  /// Set the SFX of a SpEffect's VFX, respawning the effect
+  SetSpEffectSfx {
+    sp_effect: u32,
+    #[arg(allow_negative_numbers = true)]
+    sfx: i32,
+    #[arg(allow_negative_numbers = true)]
+    dmy: i16,
+    /// Also change the SpEffect's VFX ID to this
+    #[arg(long, allow_negative_numbers = true)]
+    vfx: Option<i32>,
+  },
+  /// Watch a directory and reload FXR files when they change
+  Watch {
+    dir: PathBuf,
+    /// How long to wait for more changes before reloading, in milliseconds
+    #[arg(long, default_value_t = 200)]
+    debounce: u64,
+    /// Set the resident SFX of a weapon after each reload to respawn the effect
+    #[arg(
+      long,
+      num_args = 3,
+      value_names = ["WEAPON", "SFX", "DMY"],
+      allow_negative_numbers = true,
+      conflicts_with = "sp_effect_sfx",
+    )]
+    resident_sfx: Option<Vec<i32>>,
+    /// Set the SFX of a SpEffect's VFX after each reload to respawn the effect
+    #[arg(
+      long,
+      num_args = 3,
+      value_names = ["SP_EFFECT", "SFX", "DMY"],
+      allow_negative_numbers = true,
+    )]
+    sp_effect_sfx: Option<Vec<i32>>,
+  },
+}
+
+pub(crate) type CliResult = Result<(), Box<dyn Error>>;
+
+/// Reads the FXR ID from the header of an FXR file.
+pub(crate) fn fxr_id(fxr: &[u8]) -> Option<u32> {
+  fxr.get(0xc..0x10).map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn respawn_from_args(
+  resident_sfx: Option<Vec<i32>>,
+  sp_effect_sfx: Option<Vec<i32>>,
+) -> Result<Option<Respawn>, Box<dyn Error>> {
+  if let Some(args) = resident_sfx {
+    return Ok(Some(Respawn::ResidentSfx {
+      weapon: u32::try_from(args[0]).map_err(|_| "Invalid weapon ID")?,
+      sfx: args[1],
+      dmy: args[2],
+    }));
+  }
+  if let Some(args) = sp_effect_sfx {
+    return Ok(Some(Respawn::SpEffectSfx {
+      sp_effect: u32::try_from(args[0]).map_err(|_| "Invalid SpEffect ID")?,
+      sfx: args[1],
+      dmy: i16::try_from(args[2]).map_err(|_| "Invalid dummy poly ID")?,
+    }));
+  }
+  Ok(None)
+}
+
+fn fxr_file_name(id: u32) -> String {
+  format!("f{:09}.fxr", id)
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> CliResult {
+  std::fs::write(path, bytes)
+    .map_err(|e| format!("Failed to write {}: {}", path.display(), e).into())
+}
+
+async fn reload(client: &Client, files: &[PathBuf]) -> CliResult {
+  let mut fxrs = Vec::with_capacity(files.len());
+  for path in files {
+    let bytes = std::fs::read(path)
+      .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if fxr_id(&bytes).is_none() {
+      return Err(format!("{} is not a valid FXR file", path.display()).into());
+    }
+    fxrs.push(bytes);
+  }
+
+  client.reload_fxrs(&fxrs).await?;
+  for fxr in &fxrs {
+    println!("Reloaded FXR {}", fxr_id(fxr).unwrap());
+  }
+  Ok(())
+}
+
+async fn extract(client: &Client, id: u32, output: Option<PathBuf>) -> CliResult {
+  let fxr = client.get_fxr(id).await?;
+  let path = output.unwrap_or_else(|| PathBuf::from(fxr_file_name(id)));
+  write_file(&path, &fxr)?;
+  println!("Extracted FXR {} to {}", id, path.display());
+  Ok(())
+}
+
+async fn extract_all(client: &Client, dir: &Path) -> CliResult {
+  std::fs::create_dir_all(dir)
+    .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+  let ids = client.list_fxrs().await?;
+  let mut count = 0;
+  for chunk in ids.chunks(EXTRACT_ALL_CHUNK_SIZE) {
+    let fxrs = client.get_fxrs(chunk).await?;
+    for (id, fxr) in chunk.iter().zip(fxrs) {
+      match fxr {
+        Some(fxr) => {
+          write_file(&dir.join(fxr_file_name(*id)), &fxr)?;
+          count += 1;
+        }
+        None => eprintln!("Could not extract FXR {}", id),
+      }
+    }
+  }
+  println!("Extracted {} FXR(s) to {}", count, dir.display());
+  Ok(())
+}
+
+async fn monitor(client: &Client) -> CliResult {
+  let mut events = client.events();
+  client.subscribe(&[EventTopic::FxrsChanged]).await?;
+  println!("Watching for FXRs being loaded and unloaded");
+  let mut interval = tokio::time::interval(Duration::from_secs(1));
+  loop {
+    tokio::select! {
+      event = events.recv() => {
+        match event {
+          Ok(ServerEvent::FxrsChanged { added, removed }) => {
+            for id in added {
+              println!("Loaded {}", id);
+            }
+            for id in removed {
+              println!("Unloaded {}", id);
+            }
+          }
+          Ok(ServerEvent::FxrReloaded { ids, client }) => {
+            for id in ids {
+              println!("Reloaded {} (client {})", id, client);
+            }
+          }
+          _ => {}
+        }
+      }
+      // Nothing is sent while monitoring, so reconnecting has to be done here
+      _ = interval.tick() => client.ensure_connected().await?,
+    }
+  }
+}
+
+async fn logs(client: &Client, level: LogLevel) -> CliResult {
+  let mut events = client.events();
+  client.subscribe_logs(level).await?;
+  let mut interval = tokio::time::interval(Duration::from_secs(1));
+  loop {
+    tokio::select! {
+      event = events.recv() => {
+        if let Ok(ServerEvent::Log { level, message, request_id, client: client_id, .. }) = event {
+          let level = format!("{:?}", level).to_uppercase();
+          match (request_id, client_id) {
+            (Some(request_id), Some(client)) => println!("{:>5} [client {} request {}] {}", level, client, request_id, message),
+            _ => println!("{:>5} {}", level, message),
+          }
+        }
+      }
+      _ = interval.tick() => client.ensure_connected().await?,
+    }
+  }
+}
+
+fn parse_level(level: &str) -> Result<LogLevel, String> {
+  serde_json::from_value(serde_json::Value::String(level.to_string()))
+    .map_err(|_| format!("invalid log level: {}", level))
+}
+
+/// The URLs of the servers that wrote discovery files, newest first, followed
+/// by the default URL.
+fn discovered_urls() -> Vec<String> {
+  let mut urls: Vec<String> = Discovery::read_all().into_iter().map(|server| server.url).collect();
+  if !urls.iter().any(|url| url == client::DEFAULT_URL) {
+    urls.push(client::DEFAULT_URL.to_string());
+  }
+  urls
+}
+
+/// Connects to the server at `url`, or if it isn't given, to the first
+/// discovered server that can be connected to. Discovery files left behind by
+/// games that crashed point to servers that are gone, so those are skipped.
+async fn connect(url: Option<String>, options: ClientOptions) -> Result<Client, String> {
+  let urls = match url {
+    Some(url) => vec![url],
+    None => discovered_urls(),
+  };
+  let mut last_error = None;
+  for url in urls {
+    match Client::connect_with_options(&url, options.clone()).await {
+      Ok(client) => return Ok(client),
+      // Only failures to reach the server move on to the next one, so that
+      // errors from a server that is running, like rejected tokens, are shown
+      Err(e @ ClientError::WebSocket(_)) => last_error = Some(format!("Failed to connect to {}: {}", url, e)),
+      Err(e) => return Err(format!("Failed to connect to {}: {}", url, e)),
+    }
+  }
+  Err(last_error.unwrap_or_default())
+}
+
+async fn run(cli: Cli) -> CliResult {
+  let options = ClientOptions {
+    binary_frames: cli.compress,
+    zstd: cli.compress,
+    timeout: cli.timeout.map(Duration::from_millis),
+    auth_token: cli.token,
+    ..Default::default()
+  };
+  let client = connect(cli.url, options).await?;
+
+  match cli.command {
+    Command::Reload { files } => reload(&client, &files).await,
+    Command::Extract { id, output } => extract(&client, id, output).await,
+    Command::ExtractAll { dir } => extract_all(&client, &dir).await,
+    Command::List => {
+      for id in client.list_fxrs().await? {
+        println!("{}", id);
+      }
+      Ok(())
+    }
+    Command::Info => {
+      let info = client.server_info();
+      println!("Version: {}", info.version);
+      if let Some(game) = info.game {
+        println!("Game: {}", game);
+      }
+      if let Some(features) = info.features {
+        println!("Reload: {}", features.reload);
+        println!("Params: {}", features.params);
+        println!("Extract: {}", features.extract);
+      }
+      if let Some(protocol_version) = info.protocol_version {
+        println!("Protocol version: {}", protocol_version);
+        let capabilities = client.capabilities().await?;
+        let requests: Vec<_> = capabilities.requests.iter().map(|request| request.name.as_str()).collect();
+        println!("Requests: {}", requests.join(", "));
+        println!("Features: {}", capabilities.features.join(", "));
+        if !capabilities.negotiated.is_empty() {
+          println!("Negotiated: {}", capabilities.negotiated.join(", "));
+        }
+      }
+      Ok(())
+    }
+    Command::Monitor => monitor(&client).await,
+    Command::Logs { level } => logs(&client, level).await,
+    Command::Stats => {
+      let stats = client.stats().await?;
+      println!("Uptime: {} s", stats.uptime_secs);
+      println!("Connected clients: {} ({} total)", stats.connected_clients, stats.total_connections);
+      println!("Received: {} bytes, sent: {} bytes", stats.bytes_received, stats.bytes_sent);
+      println!("FXRs reloaded: {}, extracted: {}", stats.fxrs_reloaded, stats.fxrs_extracted);
+      if stats.events_dropped > 0 {
+        println!("Events dropped for slow clients: {}", stats.events_dropped);
+      }
+      if let Some(resolve_us) = stats.fxr_manager_resolve_us {
+        println!("FXR manager found in {:.1} ms", resolve_us as f64 / 1000.0);
+      }
+      for request in stats.requests {
+        let failed: u64 = request.failures.values().sum();
+        let average_ms = request.latency.sum_us as f64 / request.count.max(1) as f64 / 1000.0;
+        println!("{}: {} requests, {} failed, {:.1} ms on average", request.request_type, request.count, failed, average_ms);
+      }
+      Ok(())
+    }
+    Command::Shutdown => {
+      client.shutdown().await?;
+      println!("The server is shutting down");
+      Ok(())
+    }
+    Command::SetResidentSfx { weapon, sfx, dmy } => {
+      client.set_resident_sfx(weapon, sfx, dmy).await?;
+      println!("Set resident SFX for weapon {}", weapon);
+      Ok(())
+    }
+    Command::SetSpEffectSfx { sp_effect, sfx, dmy, vfx } => {
+      client.set_sp_effect_sfx(sp_effect, sfx, dmy, vfx).await?;
+      println!("Updated SFX for SpEffect {}", sp_effect);
+      Ok(())
+    }
+    Command::Watch { dir, debounce, resident_sfx, sp_effect_sfx } => {
+      let options = WatchOptions {
+        dir,
+        debounce: Duration::from_millis(debounce),
+        respawn: respawn_from_args(resident_sfx, sp_effect_sfx)?,
+      };
+      watch::watch(&client, options).await
+    }
+  }
+}
+
+#[tokio::main]
+async fn main() {
+  let cli = Cli::parse();
+  if let Err(e) = run(cli).await {
+    eprintln!("{}", e);
+    process::exit(1);
+  }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use client::Client;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use crate::{fxr_id, CliResult};
+
+/// A param edit to make after each reload to respawn the effect.
+pub enum Respawn {
+  ResidentSfx { weapon: u32, sfx: i32, dmy: i32 },
+  SpEffectSfx { sp_effect: u32, sfx: i32, dmy: i16 },
+}
+
+pub struct WatchOptions {
+  pub dir: PathBuf,
+  pub debounce: Duration,
+  pub respawn: Option<Respawn>,
+}
+
+fn is_fxr_path(path: &Path) -> bool {
+  path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("fxr"))
+}
+
+/// Watches a directory and reloads FXR files when they change. Changes are
+/// collected until there has been no change for the debounce duration, and
+/// are then reloaded together in a single request.
+pub async fn watch(client: &Client, options: WatchOptions) -> CliResult {
+  let (tx, mut rx) = mpsc::unbounded_channel::<PathBuf>();
+  let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+    match res {
+      Ok(event) => {
+        if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+          for path in event.paths.into_iter().filter(|p| is_fxr_path(p)) {
+            let _ = tx.send(path);
+          }
+        }
+      }
+      Err(e) => eprintln!("Watch error: {}", e),
+    }
+  }).map_err(|e| format!("Failed to create file watcher: {}", e))?;
+  watcher.watch(&options.dir, RecursiveMode::Recursive)
+    .map_err(|e| format!("Failed to watch {}: {}", options.dir.display(), e))?;
+  println!("Watching {} for FXR changes", options.dir.display());
+
+  while let Some(path) = rx.recv().await {
+    let mut changed = BTreeSet::from([path]);
+    while let Ok(Some(path)) = tokio::time::timeout(options.debounce, rx.recv()).await {
+      changed.insert(path);
+    }
+    reload_changed(client, &changed, options.respawn.as_ref()).await;
+  }
+  Ok(())
+}
+
+async fn reload_changed(client: &Client, paths: &BTreeSet<PathBuf>, respawn: Option<&Respawn>) {
+  let mut fxrs = Vec::with_capacity(paths.len());
+  let mut ids = Vec::with_capacity(paths.len());
+  for path in paths {
+    let bytes = match std::fs::read(path) {
+      Ok(bytes) => bytes,
+      // The file was removed or renamed again before the debounce ran out
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+      Err(e) => {
+        eprintln!("Failed to read {}: {}", path.display(), e);
+        continue;
+      }
+    };
+    match fxr_id(&bytes) {
+      Some(id) => {
+        ids.push(id);
+        fxrs.push(bytes);
+      }
+      None => eprintln!("Skipping {}: not a valid FXR file", path.display()),
+    }
+  }
+  if fxrs.is_empty() {
+    return;
+  }
+
+  if let Err(e) = client.reload_fxrs(&fxrs).await {
+    eprintln!("Failed to reload FXRs: {}", e);
+    return;
+  }
+  for id in &ids {
+    println!("Reloaded FXR {}", id);
+  }
+
+  let result = match respawn {
+    Some(Respawn::ResidentSfx { weapon, sfx, dmy }) => client.set_resident_sfx(*weapon, *sfx, *dmy).await,
+    Some(Respawn::SpEffectSfx { sp_effect, sfx, dmy }) => client.set_sp_effect_sfx(*sp_effect, *sfx, *dmy, None).await,
+    None => return,
+  };
+  if let Err(e) = result {
+    eprintln!("Failed to respawn effect: {}", e);
+  }
+}
@@ -0,0 +1,127 @@
+//! The config file, which is read from the DLL's directory. Options are
+//! layered and validated by the `settings` crate, and this module keeps track
+//! of the current config and reloads it when the file changes.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+
+use super::{get_dll_dir_path, logging, shutdown, tls};
+
+pub use settings::Config;
+
+const FILE_NAME: &str = "fxr_ws_reloader_config.json";
+
+/// How long to wait for more changes to the config file before reloading it,
+/// since editors often save files in several steps.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+static CURRENT: Lazy<RwLock<Arc<Config>>> = Lazy::new(Default::default);
+
+/// Loads the TLS certificate and key of a config, if they are set.
+pub fn tls_acceptor(config: &Config) -> Result<Option<TlsAcceptor>, String> {
+  match (&config.tls_cert, &config.tls_key) {
+    (None, None) => Ok(None),
+    (Some(cert), Some(key)) => {
+      let dir = get_dll_dir_path().unwrap_or_default();
+      tls::acceptor(&dir.join(cert), &dir.join(key)).map(Some)
+    }
+    _ => Err("tls_cert and tls_key must be set together".to_string()),
+  }
+}
+
+/// The path of the config file.
+pub fn path() -> PathBuf {
+  get_dll_dir_path()
+    .map(|dir| dir.join(FILE_NAME))
+    .unwrap_or_else(|| PathBuf::from(FILE_NAME))
+}
+
+/// The config new connections should use.
+pub fn current() -> Arc<Config> {
+  CURRENT.read().unwrap().clone()
+}
+
+/// Replaces the config new connections use.
+pub fn set(config: Config) -> Arc<Config> {
+  let config = Arc::new(config);
+  *CURRENT.write().unwrap() = config.clone();
+  config
+}
+
+/// Loads the config file, with environment variables and the section for the
+/// game applied on top of it, and returns what was wrong with it.
+pub fn load(path: &Path, game: Option<&str>) -> (Config, Vec<String>) {
+  settings::load(path, game, |var| std::env::var(var).ok())
+}
+
+/// Reloads the config when the file changes. Only the options that are read
+/// when clients connect are changed, and they only apply to new connections.
+/// The others require restarting the game.
+pub async fn watch(path: PathBuf, game: Option<&'static str>) {
+  let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+  let file_name = path.file_name().map(ToOwned::to_owned);
+  let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+    let Ok(event) = res else {
+      return;
+    };
+    let changed = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+      && event.paths.iter().any(|path| path.file_name() == file_name.as_deref());
+    if changed {
+      let _ = tx.send(());
+    }
+  });
+  let mut watcher = match watcher {
+    Ok(watcher) => watcher,
+    Err(e) => {
+      error!("Failed to watch {}: {}", path.display(), e);
+      return;
+    }
+  };
+  // Editors often replace files instead of writing to them, which would stop
+  // a watch on the file itself, so its directory is watched instead
+  let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+  if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+    error!("Failed to watch {}: {}", dir.display(), e);
+    return;
+  }
+
+  loop {
+    tokio::select! {
+      changed = rx.recv() => if changed.is_none() {
+        break;
+      },
+      _ = shutdown::started() => break,
+    }
+    while let Ok(Some(())) = tokio::time::timeout(RELOAD_DEBOUNCE, rx.recv()).await {}
+    reload(&path, game);
+  }
+}
+
+fn reload(path: &Path, game: Option<&str>) {
+  let (loaded, errors) = load(path, game);
+  for error in &errors {
+    warn!("Config error: {}", error);
+  }
+  let current = current();
+  let restart_required = loaded.restart_required(&current);
+  if !restart_required.is_empty() {
+    warn!("Restart the game to apply the changes to {}", restart_required.join(", "));
+  }
+  logging::set_level(loaded.log_level);
+  set(Config {
+    allowed_peers: loaded.allowed_peers,
+    max_message_size: loaded.max_message_size,
+    ping_interval_secs: loaded.ping_interval_secs,
+    idle_timeout_secs: loaded.idle_timeout_secs,
+    auth: loaded.auth,
+    log_level: loaded.log_level,
+    ..Config::clone(&current)
+  });
+  info!("Reloaded {}", path.display());
+}
@@ -0,0 +1,37 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+use protocol::Discovery;
+use tracing::{error, info};
+
+/// Writes the discovery file for this process so that clients can find the
+/// server.
+pub fn write(addr: SocketAddr, tls: bool, game: Option<&str>) {
+  // Clients can't connect to an unspecified address, but the server can be
+  // reached through loopback in that case
+  let host = match addr.ip() {
+    ip if ip.is_unspecified() && ip.is_ipv4() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    ip if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ip => ip,
+  };
+  let discovery = Discovery {
+    pid: std::process::id(),
+    url: format!("{}://{}", if tls { "wss" } else { "ws" }, SocketAddr::new(host, addr.port())),
+    port: addr.port(),
+    game: game.map(str::to_string),
+    version: env!("CARGO_PKG_VERSION").to_string(),
+    started_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default(),
+  };
+  let path = Discovery::path(discovery.pid);
+  let result = path.parent()
+    .map_or(Ok(()), std::fs::create_dir_all)
+    .and_then(|_| std::fs::write(&path, serde_json::to_vec_pretty(&discovery).unwrap()));
+  match result {
+    Ok(()) => info!("Wrote discovery file {}", path.display()),
+    Err(e) => error!("Failed to write discovery file {}: {}", path.display(), e),
+  }
+}
+
+/// Removes the discovery file for this process, if there is one.
+pub fn remove() {
+  let _ = std::fs::remove_file(Discovery::path(std::process::id()));
+}
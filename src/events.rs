@@ -0,0 +1,130 @@
+use std::collections::{BTreeSet, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use patcher::game::game_data::GameData;
+use protocol::{EventTopic, LogLevel, ServerEvent};
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// How often the loaded FXRs are checked for changes while any client is
+/// subscribed to `fxrs_changed` events.
+const FXR_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static EVENTS: Lazy<broadcast::Sender<ServerEvent>> = Lazy::new(|| broadcast::channel(256).0);
+static FXR_SUBSCRIBERS: AtomicUsize = AtomicUsize::new(0);
+static LOG_SUBSCRIBERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sends an event to every connection. Each connection only passes it on to
+/// its client if the client is subscribed to the event's topic.
+pub fn publish(event: ServerEvent) {
+  // This only fails if there are no connections
+  let _ = EVENTS.send(event);
+}
+
+pub fn receiver() -> broadcast::Receiver<ServerEvent> {
+  EVENTS.subscribe()
+}
+
+/// Whether any client is subscribed to log records. Records aren't turned into
+/// events otherwise.
+pub fn logs_wanted() -> bool {
+  LOG_SUBSCRIBERS.load(Ordering::SeqCst) > 0
+}
+
+/// How many connections are subscribed to a topic, for topics whose events
+/// are only produced while someone is listening.
+fn subscribers(topic: EventTopic) -> &'static AtomicUsize {
+  match topic {
+    EventTopic::FxrsChanged => &FXR_SUBSCRIBERS,
+    EventTopic::Logs => &LOG_SUBSCRIBERS,
+  }
+}
+
+/// The topics a connection is subscribed to.
+pub struct Subscriptions {
+  topics: Mutex<HashSet<EventTopic>>,
+  /// The lowest level of log records to send.
+  log_level: Mutex<LogLevel>,
+}
+
+impl Default for Subscriptions {
+  fn default() -> Self {
+    Self {
+      topics: Mutex::default(),
+      log_level: Mutex::new(LogLevel::Info),
+    }
+  }
+}
+
+impl Subscriptions {
+  pub fn subscribe(&self, topic: EventTopic) {
+    if self.topics.lock().unwrap().insert(topic) {
+      subscribers(topic).fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  /// Subscribes to log records of the given level and above.
+  pub fn subscribe_logs(&self, level: LogLevel) {
+    *self.log_level.lock().unwrap() = level;
+    self.subscribe(EventTopic::Logs);
+  }
+
+  pub fn unsubscribe(&self, topic: EventTopic) {
+    if self.topics.lock().unwrap().remove(&topic) {
+      subscribers(topic).fetch_sub(1, Ordering::SeqCst);
+    }
+  }
+
+  /// Whether the event should be sent to this connection's client.
+  pub fn wants(&self, event: &ServerEvent) -> bool {
+    let Some(topic) = event.topic() else {
+      return true;
+    };
+    if !self.topics.lock().unwrap().contains(&topic) {
+      return false;
+    }
+    match event {
+      ServerEvent::Log { level, .. } => *level <= *self.log_level.lock().unwrap(),
+      _ => true,
+    }
+  }
+}
+
+impl Drop for Subscriptions {
+  fn drop(&mut self) {
+    for topic in self.topics.get_mut().unwrap().drain() {
+      subscribers(topic).fetch_sub(1, Ordering::SeqCst);
+    }
+  }
+}
+
+/// Publishes `fxrs_changed` events when FXRs are loaded or unloaded. The game
+/// doesn't tell us when this happens, so the list of loaded FXRs is polled,
+/// but only while someone is subscribed.
+pub async fn watch_fxrs(game_data: GameData) {
+  let mut known: Option<BTreeSet<u32>> = None;
+  loop {
+    tokio::time::sleep(FXR_POLL_INTERVAL).await;
+    if FXR_SUBSCRIBERS.load(Ordering::SeqCst) == 0 {
+      known = None;
+      continue;
+    }
+
+    // This fails while the game is loading, so just try again later
+    let Ok(ids) = patcher::list_ids(&game_data) else {
+      continue;
+    };
+    let ids: BTreeSet<u32> = ids.into_iter().collect();
+    if let Some(known) = &known {
+      let added: Vec<u32> = ids.difference(known).copied().collect();
+      let removed: Vec<u32> = known.difference(&ids).copied().collect();
+      if !added.is_empty() || !removed.is_empty() {
+        debug!("FXRs changed: {} loaded, {} unloaded", added.len(), removed.len());
+        publish(ServerEvent::FxrsChanged { added, removed });
+      }
+    }
+    known = Some(ids);
+  }
+}
@@ -0,0 +1,237 @@
+//! Newline-delimited JSON transport for clients that can't use WebSockets.
+//! Each line a client sends is a request, and each line the server sends is a
+//! response or an event, the same as the WebSocket server's text messages.
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use protocol::{ErrorCode, Response, ResponseError, ServerEvent};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info, warn};
+
+use super::{
+  accept_tls, config, metrics, server_info, shutdown, Attachments, Config, Connection, Encoding, Session, GAME_DATA,
+  NEXT_CLIENT_ID,
+};
+
+/// Serves JSON lines over TCP on the WebSocket server's bind address.
+pub async fn serve_tcp(config: Arc<Config>, port: u16, tls: Option<TlsAcceptor>) {
+  let addr = SocketAddr::new(config.bind, port);
+  let listener = match TcpListener::bind(&addr).await {
+    Ok(listener) => listener,
+    Err(e) => {
+      error!("Failed to start the JSON lines server on {}: {}", addr, e);
+      return;
+    }
+  };
+  info!("JSON lines server listening on: {}{}", addr, if tls.is_some() { " (TLS)" } else { "" });
+
+  loop {
+    let (stream, peer) = tokio::select! {
+      accepted = listener.accept() => match accepted {
+        Ok(accepted) => accepted,
+        Err(_) => break,
+      },
+      _ = shutdown::started() => break,
+    };
+    let config = config::current();
+    if !config.allows_peer(peer.ip()) {
+      warn!("Rejected connection from {}, which is not in allowed_peers", peer);
+      continue;
+    }
+    match &tls {
+      Some(tls) => {
+        let tls = tls.clone();
+        tokio::spawn(async move {
+          if let Some(stream) = accept_tls(&tls, stream, peer).await {
+            handle_stream(stream, peer.to_string(), config).await;
+          }
+        });
+      }
+      None => {
+        tokio::spawn(handle_stream(stream, peer.to_string(), config));
+      }
+    }
+  }
+}
+
+/// Serves JSON lines over a named pipe. Only local clients can connect.
+#[cfg(windows)]
+pub async fn serve_local(name: String) {
+  use tokio::net::windows::named_pipe::ServerOptions;
+
+  let create = |first| ServerOptions::new()
+    .first_pipe_instance(first)
+    .reject_remote_clients(true)
+    .create(&name);
+  let mut server = match create(true) {
+    Ok(server) => server,
+    Err(e) => {
+      error!("Failed to create the named pipe {}: {}", name, e);
+      return;
+    }
+  };
+  info!("JSON lines server listening on: {}", name);
+
+  loop {
+    tokio::select! {
+      connected = server.connect() => {
+        if let Err(e) = connected {
+          warn!("Error accepting a connection on {}: {}", name, e);
+        }
+      }
+      _ = shutdown::started() => return,
+    }
+    // A new instance has to be created before the connected one is handed
+    // off so that there is always one for clients to connect to
+    let next = match create(false) {
+      Ok(next) => next,
+      Err(e) => {
+        error!("Failed to create the named pipe {}: {}", name, e);
+        return;
+      }
+    };
+    let connected = std::mem::replace(&mut server, next);
+    tokio::spawn(handle_stream(connected, name.clone(), config::current()));
+  }
+}
+
+/// Serves JSON lines over a Unix domain socket.
+#[cfg(unix)]
+pub async fn serve_local(path: String) {
+  use std::os::unix::fs::FileTypeExt;
+  use tokio::net::UnixListener;
+
+  // Sockets left behind by a previous run would make binding fail, but
+  // anything else at the path is left alone
+  if std::fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+    let _ = std::fs::remove_file(&path);
+  }
+  let listener = match UnixListener::bind(&path) {
+    Ok(listener) => listener,
+    Err(e) => {
+      error!("Failed to create the socket {}: {}", path, e);
+      return;
+    }
+  };
+  info!("JSON lines server listening on: {}", path);
+
+  loop {
+    let stream = tokio::select! {
+      accepted = listener.accept() => match accepted {
+        Ok((stream, _)) => stream,
+        Err(_) => break,
+      },
+      _ = shutdown::started() => break,
+    };
+    tokio::spawn(handle_stream(stream, path.clone(), config::current()));
+  }
+  let _ = std::fs::remove_file(&path);
+}
+
+async fn handle_stream<S>(stream: S, peer: String, config: Arc<Config>)
+where
+  S: AsyncRead + AsyncWrite + Send + 'static,
+{
+  let _open = shutdown::connection();
+  let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+  info!("New JSON lines connection from {} (client {})", peer, client_id);
+  // There are no handshake headers, so clients authenticate with an auth
+  // request
+  let role = config.auth.initial_role();
+  let (read, mut write) = tokio::io::split(stream);
+  let mut read = BufReader::new(read);
+
+  let server_info = serde_json::to_string(&ServerEvent::ServerInfo(server_info(client_id, role))).unwrap();
+  if let Err(e) = write_line(&mut write, &server_info).await {
+    warn!("Failed to send server info: {}", e);
+    return;
+  }
+  let Some(game_data) = GAME_DATA.get().copied() else {
+    return;
+  };
+  info!("Client {} connected with the {:?} role", client_id, role);
+  let _connected = metrics::connected();
+
+  let (response_tx, mut response_rx) = mpsc::channel::<(String, Message)>(32);
+  let write_handle = tokio::spawn(async move {
+    while let Some((id, message)) = response_rx.recv().await {
+      let text = match message {
+        Message::Text(text) => text,
+        Message::Close(_) => break,
+        // Binary frames are never sent since the session doesn't allow them,
+        // and there is nothing to ping
+        _ => continue,
+      };
+      metrics::sent(text.len() + 1);
+      if let Err(e) = write_line(&mut write, &text).await {
+        warn!("Error sending response for request {}: {}", id, e);
+        break;
+      }
+    }
+    let _ = write.shutdown().await;
+  });
+
+  let session = Session::new(client_id, game_data, role, false, false);
+  let connection = Connection::start(session, Encoding::default(), config.clone(), response_tx.clone());
+
+  // One more byte than the limit is read so that lines that are too long can
+  // be told apart from lines that are exactly as long as the limit
+  let limit = config.max_message_size as u64 + 1;
+  let mut line = Vec::new();
+  loop {
+    line.clear();
+    let mut limited = (&mut read).take(limit);
+    let read = tokio::select! {
+      read = limited.read_until(b'\n', &mut line) => read,
+      // The connection is closed once the requests that are being handled
+      // have finished
+      _ = shutdown::started() => break,
+    };
+    match read {
+      Ok(0) => break,
+      Ok(_) => {}
+      Err(e) => {
+        warn!("Error reading from client {}: {}", client_id, e);
+        break;
+      }
+    }
+    metrics::received(line.len());
+    if line.len() as u64 == limit && !line.ends_with(b"\n") {
+      warn!("Client {} sent a message that is too large", client_id);
+      metrics::invalid_request(ErrorCode::InvalidRequest);
+      connection.send_error(Response {
+        request_id: ":ERROR:".to_string(),
+        success: false,
+        message: format!("Messages can't be longer than {} bytes", config.max_message_size),
+        data: None,
+        error: Some(ResponseError::new(ErrorCode::InvalidRequest)),
+      }).await;
+      break;
+    }
+
+    let text = String::from_utf8_lossy(&line);
+    let text = text.trim_end_matches(['\r', '\n']);
+    if text.trim().is_empty() {
+      continue;
+    }
+    if !connection.handle_message(text.to_string(), Attachments::default()).await {
+      break;
+    }
+  }
+
+  connection.finish().await;
+  drop(response_tx);
+  let _ = write_handle.await;
+}
+
+async fn write_line<W: AsyncWrite + Unpin>(write: &mut W, text: &str) -> std::io::Result<()> {
+  write.write_all(text.as_bytes()).await?;
+  write.write_all(b"\n").await?;
+  // TLS streams buffer writes until they are flushed
+  write.flush().await
+}
@@ -0,0 +1,176 @@
+//! Log records go to the console, if there is one, to a log file in the
+//! DLL's directory that is rotated daily, and to clients subscribed to them.
+
+use std::fmt::{Debug, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use once_cell::sync::OnceCell;
+use protocol::{LogLevel, Response, ServerEvent};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::Attributes;
+use tracing::{info, info_span, warn, Event, Id, Level, Span, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use super::{events, get_dll_dir_path, Config};
+
+const FILE_PREFIX: &str = "fxr_ws_reloader";
+const FILE_SUFFIX: &str = "log";
+
+static LEVEL: OnceCell<reload::Handle<LevelFilter, Registry>> = OnceCell::new();
+
+/// Starts sending log records to the console and the log file. Records below
+/// the config's level are dropped.
+pub fn init(config: &Config) {
+  let (level, handle) = reload::Layer::new(config.log_level);
+  let dir = get_dll_dir_path().unwrap_or_default();
+  let file = RollingFileAppender::builder()
+    .rotation(Rotation::DAILY)
+    .filename_prefix(FILE_PREFIX)
+    .filename_suffix(FILE_SUFFIX)
+    .max_log_files(config.log_max_files.max(1))
+    .build(&dir);
+  let file = match file {
+    Ok(file) => Some(file),
+    Err(e) => {
+      eprintln!("Failed to create a log file in {}: {}", dir.display(), e);
+      None
+    }
+  };
+  let registered = tracing_subscriber::registry()
+    .with(level)
+    .with(fmt::layer().with_ansi(false).with_writer(std::io::stdout))
+    .with(file.map(|file| fmt::layer().with_ansi(false).with_writer(file)))
+    .with(EventLayer)
+    .try_init();
+  if registered.is_ok() {
+    let _ = LEVEL.set(handle);
+  }
+}
+
+/// Changes the level of the records that are logged.
+pub fn set_level(level: LevelFilter) {
+  if let Some(handle) = LEVEL.get() {
+    if let Err(e) = handle.reload(level) {
+      warn!("Failed to change the log level: {}", e);
+    }
+  }
+}
+
+/// The span that everything logged while handling a request is recorded in.
+pub fn request_span(client_id: u64, request_id: &str, request_type: &str) -> Span {
+  info_span!("request", client = client_id, request_id, request_type)
+}
+
+/// Logs how long a request took and whether it succeeded.
+pub fn request_finished(span: &Span, response: &Response, duration: Duration) {
+  let duration_ms = duration.as_secs_f64() * 1000.0;
+  if response.success {
+    info!(parent: span, duration_ms, "Request succeeded");
+  } else if let Some(error) = &response.error {
+    info!(parent: span, duration_ms, code = ?error.code, "Request failed: {}", response.message);
+  } else {
+    info!(parent: span, duration_ms, "Request failed: {}", response.message);
+  }
+}
+
+/// Publishes log records as `log` events while any client is subscribed to
+/// them.
+struct EventLayer;
+
+/// The request a span was created for, from the fields of `request_span`.
+#[derive(Default)]
+struct RequestFields {
+  request_id: Option<String>,
+  client: Option<u64>,
+}
+
+impl Visit for RequestFields {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    if field.name() == "request_id" {
+      self.request_id = Some(value.to_string());
+    }
+  }
+
+  fn record_u64(&mut self, field: &Field, value: u64) {
+    if field.name() == "client" {
+      self.client = Some(value);
+    }
+  }
+
+  fn record_debug(&mut self, _field: &Field, _value: &dyn Debug) {}
+}
+
+/// Formats a record's message followed by its other fields, like the console
+/// does.
+#[derive(Default)]
+struct Message {
+  message: String,
+  fields: String,
+}
+
+impl Visit for Message {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    if field.name() == "message" {
+      self.message = value.to_string();
+    } else {
+      let _ = write!(self.fields, " {}={}", field.name(), value);
+    }
+  }
+
+  fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+    if field.name() == "message" {
+      let _ = write!(self.message, "{:?}", value);
+    } else {
+      let _ = write!(self.fields, " {}={:?}", field.name(), value);
+    }
+  }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for EventLayer {
+  fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+    if attrs.metadata().name() != "request" {
+      return;
+    }
+    let mut fields = RequestFields::default();
+    attrs.record(&mut fields);
+    if let Some(span) = ctx.span(id) {
+      span.extensions_mut().insert(fields);
+    }
+  }
+
+  fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+    if !events::logs_wanted() {
+      return;
+    }
+    let mut message = Message::default();
+    event.record(&mut message);
+    let (request_id, client) = ctx.event_scope(event)
+      .and_then(|scope| {
+        scope.from_root().find_map(|span| {
+          span.extensions().get::<RequestFields>().map(|fields| (fields.request_id.clone(), fields.client))
+        })
+      })
+      .unwrap_or_default();
+    events::publish(ServerEvent::Log {
+      level: log_level(*event.metadata().level()),
+      timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or_default(),
+      message: message.message + &message.fields,
+      request_id,
+      client,
+    });
+  }
+}
+
+fn log_level(level: Level) -> LogLevel {
+  match level {
+    Level::ERROR => LogLevel::Error,
+    Level::WARN => LogLevel::Warn,
+    Level::INFO => LogLevel::Info,
+    Level::DEBUG => LogLevel::Debug,
+    Level::TRACE => LogLevel::Trace,
+  }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use protocol::{ErrorCode, LatencyHistogram, RequestStats, Stats};
+
+/// Upper bounds of the latency histogram buckets, in microseconds.
+const LATENCY_BOUNDS_US: &[u64] = &[
+  1_000, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+static CONNECTED_CLIENTS: AtomicU64 = AtomicU64::new(0);
+static TOTAL_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static FXRS_RELOADED: AtomicU64 = AtomicU64::new(0);
+static FXRS_EXTRACTED: AtomicU64 = AtomicU64::new(0);
+static EVENTS_DROPPED: AtomicU64 = AtomicU64::new(0);
+static REQUESTS: Lazy<Mutex<BTreeMap<&'static str, RequestMetrics>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Default)]
+struct RequestMetrics {
+  count: u64,
+  failures: BTreeMap<ErrorCode, u64>,
+  buckets: [u64; LATENCY_BOUNDS_US.len() + 1],
+  sum_us: u64,
+}
+
+/// Starts the uptime clock.
+pub fn start() {
+  Lazy::force(&STARTED);
+}
+
+/// Counts a client as connected until the returned value is dropped.
+pub fn connected() -> ConnectedClient {
+  CONNECTED_CLIENTS.fetch_add(1, Ordering::Relaxed);
+  TOTAL_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+  ConnectedClient(())
+}
+
+pub struct ConnectedClient(());
+
+impl Drop for ConnectedClient {
+  fn drop(&mut self) {
+    CONNECTED_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+pub fn received(bytes: usize) {
+  BYTES_RECEIVED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn sent(bytes: usize) {
+  BYTES_SENT.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn fxrs_reloaded(count: usize) {
+  FXRS_RELOADED.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+pub fn fxrs_extracted(count: usize) {
+  FXRS_EXTRACTED.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Counts events a connection skipped because its client couldn't keep up.
+/// These aren't logged, since the log record would itself be an event for
+/// clients subscribed to logs.
+pub fn events_dropped(count: u64) {
+  EVENTS_DROPPED.fetch_add(count, Ordering::Relaxed);
+}
+
+/// The request type that requests which couldn't be parsed are recorded as.
+const INVALID_REQUEST_TYPE: &str = "invalid";
+
+/// Records a request that was handled, or that was rejected without being
+/// handled, like requests clients don't have permission for.
+pub fn request(type_name: &'static str, duration: Duration, error: Option<ErrorCode>) {
+  let duration_us = duration.as_micros().min(u64::MAX as u128) as u64;
+  let bucket = LATENCY_BOUNDS_US.iter()
+    .position(|&bound| duration_us <= bound)
+    .unwrap_or(LATENCY_BOUNDS_US.len());
+  let mut requests = REQUESTS.lock().unwrap();
+  let metrics = requests.entry(type_name).or_default();
+  metrics.count += 1;
+  metrics.buckets[bucket] += 1;
+  metrics.sum_us = metrics.sum_us.saturating_add(duration_us);
+  if let Some(code) = error {
+    *metrics.failures.entry(code).or_default() += 1;
+  }
+}
+
+/// Records a request that couldn't be parsed, or a message that couldn't be
+/// read as a request at all.
+pub fn invalid_request(code: ErrorCode) {
+  request(INVALID_REQUEST_TYPE, Duration::ZERO, Some(code));
+}
+
+pub fn stats() -> Stats {
+  let requests = REQUESTS.lock().unwrap().iter()
+    .map(|(type_name, metrics)| RequestStats {
+      request_type: type_name.to_string(),
+      count: metrics.count,
+      failures: metrics.failures.clone(),
+      latency: LatencyHistogram {
+        bounds_us: LATENCY_BOUNDS_US.to_vec(),
+        counts: metrics.buckets.to_vec(),
+        sum_us: metrics.sum_us,
+      },
+    })
+    .collect();
+  Stats {
+    uptime_secs: STARTED.elapsed().as_secs(),
+    connected_clients: CONNECTED_CLIENTS.load(Ordering::Relaxed),
+    total_connections: TOTAL_CONNECTIONS.load(Ordering::Relaxed),
+    bytes_received: BYTES_RECEIVED.load(Ordering::Relaxed),
+    bytes_sent: BYTES_SENT.load(Ordering::Relaxed),
+    fxrs_reloaded: FXRS_RELOADED.load(Ordering::Relaxed),
+    fxrs_extracted: FXRS_EXTRACTED.load(Ordering::Relaxed),
+    events_dropped: EVENTS_DROPPED.load(Ordering::Relaxed),
+    fxr_manager_resolve_us: patcher::game::game_data::fxr_manager_resolve_time()
+      .map(|time| time.as_micros() as u64),
+    requests,
+  }
+}
+
+/// The stats in the Prometheus text format.
+pub fn prometheus() -> String {
+  let stats = stats();
+  let mut out = String::new();
+  let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+    let _ = writeln!(out, "# HELP fxr_reloader_{name} {help}\n# TYPE fxr_reloader_{name} {kind}\nfxr_reloader_{name} {value}");
+  };
+  metric("uptime_seconds", "gauge", "Time since the server started.", stats.uptime_secs);
+  metric("connected_clients", "gauge", "Clients that are connected right now.", stats.connected_clients);
+  metric("connections_total", "counter", "Connections accepted since the server started.", stats.total_connections);
+  metric("received_bytes_total", "counter", "Size of all messages received from clients.", stats.bytes_received);
+  metric("sent_bytes_total", "counter", "Size of all messages sent to clients.", stats.bytes_sent);
+  metric("fxrs_reloaded_total", "counter", "FXRs that were reloaded.", stats.fxrs_reloaded);
+  metric("fxrs_extracted_total", "counter", "FXRs that were extracted.", stats.fxrs_extracted);
+  metric("events_dropped_total", "counter", "Events not sent to clients that couldn't keep up.", stats.events_dropped);
+  if let Some(resolve_us) = stats.fxr_manager_resolve_us {
+    let _ = writeln!(
+      out,
+      "# HELP fxr_reloader_fxr_manager_resolve_seconds How long it took to find the game's FXR manager.\n\
+       # TYPE fxr_reloader_fxr_manager_resolve_seconds gauge\n\
+       fxr_reloader_fxr_manager_resolve_seconds {}",
+      seconds(resolve_us)
+    );
+  }
+
+  out.push_str("# HELP fxr_reloader_requests_total Requests that were handled, including failed ones.\n");
+  out.push_str("# TYPE fxr_reloader_requests_total counter\n");
+  for request in &stats.requests {
+    let _ = writeln!(out, "fxr_reloader_requests_total{{type=\"{}\"}} {}", request.request_type, request.count);
+  }
+  out.push_str("# HELP fxr_reloader_request_failures_total Requests that failed, by error code.\n");
+  out.push_str("# TYPE fxr_reloader_request_failures_total counter\n");
+  for request in &stats.requests {
+    for (code, count) in &request.failures {
+      let code = serde_json::to_value(code).ok()
+        .and_then(|code| code.as_str().map(str::to_string))
+        .unwrap_or_default();
+      let _ = writeln!(
+        out,
+        "fxr_reloader_request_failures_total{{type=\"{}\",code=\"{}\"}} {}",
+        request.request_type, code, count
+      );
+    }
+  }
+  out.push_str("# HELP fxr_reloader_request_duration_seconds How long requests took to handle.\n");
+  out.push_str("# TYPE fxr_reloader_request_duration_seconds histogram\n");
+  for request in &stats.requests {
+    let name = "fxr_reloader_request_duration_seconds";
+    let latency = &request.latency;
+    let mut cumulative = 0;
+    for (bound, count) in latency.bounds_us.iter().zip(&latency.counts) {
+      cumulative += count;
+      let _ = writeln!(out, "{name}_bucket{{type=\"{}\",le=\"{}\"}} {cumulative}", request.request_type, seconds(*bound));
+    }
+    let _ = writeln!(out, "{name}_bucket{{type=\"{}\",le=\"+Inf\"}} {}", request.request_type, request.count);
+    let _ = writeln!(out, "{name}_sum{{type=\"{}\"}} {}", request.request_type, seconds(latency.sum_us));
+    let _ = writeln!(out, "{name}_count{{type=\"{}\"}} {}", request.request_type, request.count);
+  }
+  out
+}
+
+fn seconds(us: u64) -> f64 {
+  us as f64 / 1_000_000.0
+}
@@ -0,0 +1,396 @@
+//! A small HTTP API served on the same port as the WebSocket server, so that
+//! effects can be fetched or pushed with curl, a browser or any HTTP client.
+//! Connections are routed here unless they ask to be upgraded to WebSocket.
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use protocol::{BatchResult, ErrorCode, FxrData, Request, RequestPayload, Response, ResponseError, Role};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_tungstenite::tungstenite::http::{self, StatusCode};
+use tracing::{info, warn, Instrument};
+
+use super::{auth, handle_connection, handle_request, logging, metrics, server_info, shutdown, Attachments, Config, Session, GAME_DATA, NEXT_CLIENT_ID};
+
+/// The largest request line and headers that are accepted, in bytes.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// How long clients have to send the request line and headers.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long clients have to send the body once the headers have been read.
+const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+type HttpRequest = http::Request<()>;
+
+/// An HTTP response, always sent with `Connection: close`.
+struct HttpResponse {
+  status: StatusCode,
+  content_type: &'static str,
+  headers: Vec<(&'static str, String)>,
+  body: Vec<u8>,
+}
+
+impl HttpResponse {
+  fn json(status: StatusCode, value: &impl serde::Serialize) -> Self {
+    Self {
+      status,
+      content_type: "application/json",
+      headers: Vec::new(),
+      body: serde_json::to_vec(value).unwrap(),
+    }
+  }
+
+  /// A JSON body in the same shape as the results of batch requests.
+  fn result(response: Response) -> Self {
+    let status = response.error.as_ref().map_or(StatusCode::OK, |error| status_for(error.code));
+    let mut http_response = Self::json(status, &BatchResult::from(response));
+    if status == StatusCode::UNAUTHORIZED {
+      http_response.headers.push(("WWW-Authenticate", "Bearer".to_string()));
+    }
+    http_response
+  }
+
+  fn error(status: StatusCode, code: ErrorCode, message: impl Into<String>) -> Self {
+    Self::result(Response {
+      request_id: String::new(),
+      success: false,
+      message: message.into(),
+      data: None,
+      error: Some(ResponseError::new(code)),
+    }).with_status(status)
+  }
+
+  fn with_status(mut self, status: StatusCode) -> Self {
+    self.status = status;
+    self
+  }
+}
+
+fn status_for(code: ErrorCode) -> StatusCode {
+  match code {
+    ErrorCode::InvalidRequest | ErrorCode::InvalidParam | ErrorCode::InvalidFrame | ErrorCode::InvalidFxr => StatusCode::BAD_REQUEST,
+    ErrorCode::UnknownRequestType | ErrorCode::FxrNotFound | ErrorCode::RequestNotFound => StatusCode::NOT_FOUND,
+    ErrorCode::UnsupportedOperation => StatusCode::NOT_IMPLEMENTED,
+    ErrorCode::UnsupportedGame | ErrorCode::GameDetectionFailed | ErrorCode::CssfxMissing => StatusCode::SERVICE_UNAVAILABLE,
+    ErrorCode::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+    ErrorCode::Unauthenticated => StatusCode::UNAUTHORIZED,
+    ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+    ErrorCode::Cancelled | ErrorCode::Internal | ErrorCode::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+  }
+}
+
+/// Reads the request line and headers, then either hands the connection to
+/// the WebSocket server or answers the HTTP request.
+pub async fn handle_stream<S>(mut stream: S, peer: SocketAddr, config: Arc<Config>)
+where
+  S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+  let _open = shutdown::connection();
+  let (request, buffer, head_len) = match tokio::time::timeout(HEAD_TIMEOUT, read_head(&mut stream)).await {
+    Ok(Ok(head)) => head,
+    Ok(Err(Some(response))) => {
+      write_response(&mut stream, response).await;
+      return;
+    }
+    Ok(Err(None)) => return,
+    Err(_) => {
+      warn!("Client {} took too long to send an HTTP request", peer);
+      return;
+    }
+  };
+
+  let upgrade = request.headers().get("Upgrade")
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+  if upgrade {
+    // The handshake is read again by the WebSocket server, so it has to see
+    // everything that was read so far
+    handle_connection(Prefixed { prefix: buffer, pos: 0, stream }, peer, config).await;
+    return;
+  }
+
+  let body = match read_body(&mut stream, &request, &buffer[head_len..], config.max_message_size).await {
+    Ok(body) => body,
+    Err(response) => {
+      write_response(&mut stream, response).await;
+      return;
+    }
+  };
+  metrics::received(head_len + body.len());
+  info!("HTTP {} {} from {}", request.method(), request.uri().path(), peer);
+  let response = respond(&request, body, &config).await;
+  write_response(&mut stream, response).await;
+}
+
+/// Reads until the end of the headers. Returns the parsed request, everything
+/// that was read, and the length of the head. Errors are `None` if the client
+/// disconnected.
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<(HttpRequest, Vec<u8>, usize), Option<HttpResponse>> {
+  let mut buffer = Vec::new();
+  let mut chunk = [0; 4096];
+  loop {
+    let read = stream.read(&mut chunk).await.map_err(|_| None)?;
+    if read == 0 {
+      return Err(None);
+    }
+    buffer.extend_from_slice(&chunk[..read]);
+    match parse_head(&buffer) {
+      Ok(Some((request, head_len))) => return Ok((request, buffer, head_len)),
+      Ok(None) if buffer.len() > MAX_HEAD_SIZE => {
+        return Err(Some(HttpResponse::error(
+          StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+          ErrorCode::InvalidRequest,
+          "Request headers are too large",
+        )));
+      }
+      Ok(None) => {}
+      Err(message) => {
+        return Err(Some(HttpResponse::error(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, message)));
+      }
+    }
+  }
+}
+
+/// Parses the head of a request, or returns `None` if it isn't complete yet.
+fn parse_head(buffer: &[u8]) -> Result<Option<(HttpRequest, usize)>, String> {
+  let mut headers = [httparse::EMPTY_HEADER; 64];
+  let mut parsed = httparse::Request::new(&mut headers);
+  let head_len = match parsed.parse(buffer) {
+    Ok(httparse::Status::Complete(head_len)) => head_len,
+    Ok(httparse::Status::Partial) => return Ok(None),
+    Err(e) => return Err(format!("Invalid HTTP request: {}", e)),
+  };
+  let mut builder = http::Request::builder()
+    .method(parsed.method.unwrap_or_default())
+    .uri(parsed.path.unwrap_or_default());
+  for header in parsed.headers.iter() {
+    builder = builder.header(header.name, header.value);
+  }
+  let request = builder.body(()).map_err(|e| format!("Invalid HTTP request: {}", e))?;
+  Ok(Some((request, head_len)))
+}
+
+async fn read_body<S: AsyncRead + Unpin>(
+  stream: &mut S,
+  request: &HttpRequest,
+  received: &[u8],
+  max_size: usize,
+) -> Result<Vec<u8>, HttpResponse> {
+  if request.headers().contains_key("Transfer-Encoding") {
+    return Err(HttpResponse::error(
+      StatusCode::LENGTH_REQUIRED,
+      ErrorCode::InvalidRequest,
+      "Chunked requests are not supported, use Content-Length instead",
+    ));
+  }
+  let length = match request.headers().get("Content-Length") {
+    Some(value) => value.to_str().ok()
+      .and_then(|value| value.parse::<usize>().ok())
+      .ok_or_else(|| HttpResponse::error(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, "Invalid Content-Length"))?,
+    None => 0,
+  };
+  if length > max_size {
+    return Err(HttpResponse::error(
+      StatusCode::PAYLOAD_TOO_LARGE,
+      ErrorCode::InvalidRequest,
+      format!("Requests can't be larger than {} bytes", max_size),
+    ));
+  }
+  let mut body = received[..received.len().min(length)].to_vec();
+  let already_read = body.len();
+  body.resize(length, 0);
+  match tokio::time::timeout(BODY_TIMEOUT, stream.read_exact(&mut body[already_read..])).await {
+    Ok(Ok(_)) => Ok(body),
+    Ok(Err(_)) => Err(HttpResponse::error(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, "The request body ended early")),
+    Err(_) => Err(HttpResponse::error(
+      StatusCode::REQUEST_TIMEOUT,
+      ErrorCode::TimedOut,
+      format!("The request body took longer than {} seconds to send", BODY_TIMEOUT.as_secs()),
+    )),
+  }
+}
+
+async fn respond(request: &HttpRequest, body: Vec<u8>, config: &Config) -> HttpResponse {
+  let role = match auth::handshake_token(request) {
+    Some(token) => match config.auth.role_for_token(&token) {
+      Some(role) => role,
+      None => return HttpResponse::error(StatusCode::UNAUTHORIZED, ErrorCode::Unauthenticated, "Invalid auth token"),
+    },
+    None => config.auth.initial_role(),
+  };
+  let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+
+  let path = request.uri().path().trim_end_matches('/');
+  // There is no auth request to send later, so clients without a token can
+  // only get what the WebSocket server sends them before they authenticate
+  if role == Role::Unauthenticated && path != "/info" {
+    return HttpResponse::error(StatusCode::UNAUTHORIZED, ErrorCode::Unauthenticated, "An auth token is required");
+  }
+  let segments: Vec<&str> = path.split('/').skip(1).collect();
+  let method = request.method();
+  let mut download = None;
+  let (payload, mut attachments) = match (method, segments.as_slice()) {
+    (&http::Method::GET, ["info"]) => {
+      return HttpResponse::json(StatusCode::OK, &server_info(client_id, role));
+    }
+    (&http::Method::GET, ["metrics"]) => {
+      return HttpResponse {
+        status: StatusCode::OK,
+        content_type: "text/plain; version=0.0.4",
+        headers: Vec::new(),
+        body: metrics::prometheus().into_bytes(),
+      };
+    }
+    (&http::Method::GET, ["fxrs"]) => (RequestPayload::ListFxrs, Attachments::default()),
+    (&http::Method::GET, ["fxrs", id]) => {
+      let Ok(id) = id.parse() else {
+        return invalid_id(id);
+      };
+      // FXRs are sent back as attachments so that they don't have to be
+      // decoded from base64
+      download = Some(id);
+      (RequestPayload::GetFxr { id }, Attachments::binary(Vec::new()))
+    }
+    (&http::Method::PUT, ["fxrs", id]) => {
+      let Ok(id) = id.parse::<u32>() else {
+        return invalid_id(id);
+      };
+      let body_id = body.get(0xc..0x10).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+      if body_id != Some(id) {
+        return HttpResponse::error(
+          StatusCode::BAD_REQUEST,
+          ErrorCode::InvalidFxr,
+          format!("The request body is not FXR {}", id),
+        );
+      }
+      let fxrs = vec![FxrData::Attachment { attachment: 0 }];
+      (RequestPayload::ReloadFxrs { fxrs }, Attachments::binary(vec![body]))
+    }
+    (&http::Method::POST, ["params", "resident-sfx"]) => {
+      let payload = serde_json::from_slice::<serde_json::Value>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(|mut params| {
+          let object = params.as_object_mut().ok_or("The request body must be a JSON object")?;
+          object.insert("type".to_string(), "set_resident_sfx".into());
+          RequestPayload::from_value(params).map_err(|e| e.to_string())
+        });
+      match payload {
+        Ok(payload) => (payload, Attachments::default()),
+        Err(message) => {
+          metrics::invalid_request(ErrorCode::InvalidRequest);
+          return HttpResponse::error(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, format!("Invalid parameters: {}", message));
+        }
+      }
+    }
+    (_, ["info"] | ["metrics"] | ["fxrs"] | ["fxrs", _] | ["params", "resident-sfx"]) => {
+      let allow = match segments.as_slice() {
+        ["fxrs", _] => "GET, PUT",
+        ["params", _] => "POST",
+        _ => "GET",
+      };
+      let mut response = HttpResponse::error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        ErrorCode::InvalidRequest,
+        format!("{} is not allowed for {}", method, path),
+      );
+      response.headers.push(("Allow", allow.to_string()));
+      return response;
+    }
+    _ => {
+      return HttpResponse::error(StatusCode::NOT_FOUND, ErrorCode::UnknownRequestType, format!("Nothing is served at {}", request.uri().path()));
+    }
+  };
+
+  if !role.allows(&payload) {
+    return HttpResponse::error(StatusCode::FORBIDDEN, ErrorCode::PermissionDenied, "The auth token doesn't allow this request");
+  }
+  let Some(game_data) = GAME_DATA.get().copied() else {
+    return HttpResponse::error(StatusCode::SERVICE_UNAVAILABLE, ErrorCode::GameDetectionFailed, "Failed to detect a supported game.");
+  };
+  let request = Request {
+    request_id: format!("http-{}", client_id),
+    timeout_ms: None,
+    payload,
+  };
+
+  let session = Session::new(client_id, game_data, role, true, false);
+  let type_name = request.payload.type_name();
+  let started = Instant::now();
+  let span = logging::request_span(client_id, &request.request_id, type_name);
+  let mut response = handle_request(request, &mut attachments, &session).instrument(span.clone()).await;
+  metrics::request(type_name, started.elapsed(), response.error.as_ref().map(|error| error.code));
+  logging::request_finished(&span, &response, started.elapsed());
+  if let (true, Some(id)) = (response.success, download) {
+    return HttpResponse {
+      status: StatusCode::OK,
+      content_type: "application/octet-stream",
+      headers: vec![("Content-Disposition", format!("attachment; filename=\"f{:09}.fxr\"", id))],
+      body: attachments.to_send.swap_remove(0),
+    };
+  }
+  response.request_id.clear();
+  HttpResponse::result(response)
+}
+
+fn invalid_id(id: &str) -> HttpResponse {
+  HttpResponse::error(StatusCode::BAD_REQUEST, ErrorCode::InvalidParam, format!("Invalid FXR ID: {}", id))
+}
+
+async fn write_response<S: AsyncWrite + Unpin>(stream: &mut S, response: HttpResponse) {
+  let mut head = format!(
+    "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+    response.status.as_u16(),
+    response.status.canonical_reason().unwrap_or_default(),
+    response.content_type,
+    response.body.len(),
+  );
+  for (name, value) in &response.headers {
+    head.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  head.push_str("\r\n");
+  metrics::sent(head.len() + response.body.len());
+  let result = async {
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+  }.await;
+  if let Err(e) = result {
+    warn!("Error sending HTTP response: {}", e);
+  }
+}
+
+/// A stream that gives back bytes that were already read from it before
+/// reading any more.
+struct Prefixed<S> {
+  prefix: Vec<u8>,
+  pos: usize,
+  stream: S,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Prefixed<S> {
+  fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+    if self.pos < self.prefix.len() {
+      let len = buf.remaining().min(self.prefix.len() - self.pos);
+      buf.put_slice(&self.prefix[self.pos..self.pos + len]);
+      self.pos += len;
+      return Poll::Ready(Ok(()));
+    }
+    Pin::new(&mut self.stream).poll_read(cx, buf)
+  }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Prefixed<S> {
+  fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+    Pin::new(&mut self.stream).poll_write(cx, buf)
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Pin::new(&mut self.stream).poll_flush(cx)
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    Pin::new(&mut self.stream).poll_shutdown(cx)
+  }
+}
@@ -0,0 +1,44 @@
+//! Orderly shutdown of the servers. Once shutdown starts, listeners stop
+//! accepting connections and every connection closes after finishing the
+//! requests it is handling.
+
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+
+/// How long connections have to finish their requests once shutdown starts.
+/// Connections that take longer are dropped along with the runtime.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+static STARTED: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+static OPEN_CONNECTIONS: Lazy<watch::Sender<usize>> = Lazy::new(|| watch::channel(0).0);
+
+/// Starts shutting down. Returns false if shutdown had already started.
+pub fn start() -> bool {
+  STARTED.send_if_modified(|started| !std::mem::replace(started, true))
+}
+
+/// Completes once shutdown has started.
+pub async fn started() {
+  // The sender is static, so this can't fail
+  let _ = STARTED.subscribe().wait_for(|started| *started).await;
+}
+
+/// Completes once every connection has been closed.
+pub async fn drained() {
+  let _ = OPEN_CONNECTIONS.subscribe().wait_for(|count| *count == 0).await;
+}
+
+/// Counts as an open connection until it is dropped.
+pub struct ConnectionGuard(());
+
+pub fn connection() -> ConnectionGuard {
+  OPEN_CONNECTIONS.send_modify(|count| *count += 1);
+  ConnectionGuard(())
+}
+
+impl Drop for ConnectionGuard {
+  fn drop(&mut self) {
+    OPEN_CONNECTIONS.send_modify(|count| *count -= 1);
+  }
+}
@@ -0,0 +1,19 @@
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// Loads a PEM certificate chain and private key to serve `wss://` with.
+pub fn acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor, String> {
+  let certs = CertificateDer::pem_file_iter(cert_path)
+    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+    .map_err(|e| format!("Failed to read TLS certificate {}: {}", cert_path.display(), e))?;
+  let key = PrivateKeyDer::from_pem_file(key_path)
+    .map_err(|e| format!("Failed to read TLS key {}: {}", key_path.display(), e))?;
+  let config = ServerConfig::builder()
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+  Ok(TlsAcceptor::from(Arc::new(config)))
+}